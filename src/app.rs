@@ -7,11 +7,20 @@ use ringbuf::{Producer, RingBuffer};
 use triple_buffer::{Input, Output, TripleBuffer};
 use ulid::Ulid;
 
-use crate::engine::{self, Engine, Plugin, INSTRUMENT_TRACKS, PREVIEW_INSTRUMENTS_CACHE_SIZE};
+use crate::effects::EffectType;
+use crate::engine::{
+    self, Effect, Engine, Plugin, TrackParams, INSTRUMENT_TRACKS, MAX_BUSES, MAX_EFFECTS,
+    MAX_INSTRUMENTS, MAX_INSTRUMENT_TRACKS, NUM_SENDS, PREVIEW_INSTRUMENTS_CACHE_SIZE,
+    TICKS_PER_LINE, TOTAL_TRACKS,
+};
 use crate::files::FileBrowser;
+use crate::history::History;
+use crate::loudness::Loudness;
 use crate::params::Params;
-use crate::pattern::{Step, StepSize, MAX_PATTERNS};
-use crate::sampler::{self, Sampler, ROOT_PITCH};
+use crate::pattern::{Step, StepSize, MAX_NOTE_COLUMNS, MAX_PATTERNS};
+use crate::project::{self, EffectData, InstrumentData, PatternData, Project, TrackData};
+use crate::render::{self, RenderOptions};
+use crate::sampler::{self, Sampler, Sound, ROOT_PITCH};
 use crate::{engine::EngineCommand, pattern::Pattern};
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -21,10 +30,22 @@ use std::fmt::Display;
 use std::fmt::Formatter;
 use std::ops::Range;
 use std::sync::atomic::Ordering;
+use std::thread::{self, JoinHandle};
+
+// Loading a project can replace every device in one go, before the engine gets to process any
+// commands (the renderer doesn't run the engine at all until the project is loaded): instruments
+// are deleted and created, tracks are deleted and created, and effects are removed, added,
+// bypassed and keyed. Leave some room for previews on top of that.
+const COMMAND_QUEUE_SIZE: usize = 2 * MAX_INSTRUMENTS
+    + 2 * TOTAL_TRACKS
+    + 4 * TOTAL_TRACKS * MAX_EFFECTS
+    + 2 * PREVIEW_INSTRUMENTS_CACHE_SIZE
+    + 64;
 
 pub struct App {
     pub state: AppState,
     pub engine_state: EngineState,
+    pub project_path: Option<Utf8PathBuf>,
 
     state_buf: Input<AppState>,
     producer: Producer<EngineCommand>,
@@ -33,15 +54,73 @@ pub struct App {
     preview_cache: LruCache<Utf8PathBuf, DeviceId>,
     preview_track_id: TrackId,
     collector: basedrop::Collector,
+    history: History<Edit>,
+    /// Renders running in the background, with the file or directory they're writing to
+    renders: Vec<(Utf8PathBuf, JoinHandle<Result<()>>)>,
+}
+
+/// The part of the app state that is restored when undoing edits to patterns or the song
+struct SongState {
+    patterns: HashMap<PatternId, Arc<Pattern>>,
+    song: Vec<PatternId>,
+    loop_range: Option<(usize, usize)>,
+    selected_pattern: usize,
+}
+
+enum Edit {
+    Song(SongState),
+    /// Continuous change of a parameter, consecutive changes are merged
+    Param(DeviceId, usize, f64),
+    /// Switched parameter, e.g. mute, which is always undone on its own
+    Toggle(DeviceId, usize, f64),
+    /// Edits that are undone together
+    Group(Vec<Edit>),
 }
 
 impl App {
     pub fn send(&mut self, msg: Msg) -> Result<()> {
+        let edit = self.edit(&msg);
         self.dispatch(msg)?;
+        // Messages that didn't change anything, e.g. a rejected key, don't end up in the history
+        if let Some(edit) = edit.filter(|edit| !self.is_current(edit)) {
+            self.record(edit);
+        }
+        self.publish();
+        Ok(())
+    }
+
+    /// Returns a message describing the outcome of a render that has finished
+    pub fn finished_render(&mut self) -> Option<String> {
+        let idx = self
+            .renders
+            .iter()
+            .position(|(_, handle)| handle.is_finished())?;
+        let (path, handle) = self.renders.remove(idx);
+        let message = match handle.join() {
+            Ok(Ok(())) => format!("rendered {}", path),
+            Ok(Err(err)) => format!("error: render {}: {}", path, err),
+            Err(_) => format!("error: render {} crashed", path),
+        };
+        Some(message)
+    }
+
+    pub fn is_rendering(&self) -> bool {
+        !self.renders.is_empty()
+    }
+
+    /// Block until all renders have finished, so files aren't left half written
+    pub fn wait_for_renders(&mut self) {
+        for (_, handle) in self.renders.drain(..) {
+            let _ = handle.join();
+        }
+    }
+
+    /// Make the current state available to the engine
+    pub fn publish(&mut self) {
+        self.state.track_order = processing_order(&self.state.tracks);
         let input_buf = self.state_buf.input_buffer();
         input_buf.clone_from(&self.state);
         self.state_buf.publish();
-        Ok(())
     }
 
     fn dispatch(&mut self, msg: Msg) -> Result<()> {
@@ -49,32 +128,54 @@ impl App {
         match msg {
             Noop => {}
             Exit => {}
+            Undo => {
+                if let Some(edit) = self.history.undo() {
+                    let redo = self.revert(edit);
+                    self.history.push_redo(redo);
+                }
+            }
+            Redo => {
+                if let Some(edit) = self.history.redo() {
+                    let undo = self.revert(edit);
+                    self.history.push_undo(undo);
+                }
+            }
             TogglePlay => {
                 self.state.is_playing = !self.state.is_playing;
             }
-            SetBpm(bpm) => self.state.bpm = bpm,
+            ToggleFill => self.state.fill = !self.state.fill,
+            SetBpm(bpm) => {
+                validate_tempo(bpm, self.state.lines_per_beat)?;
+                self.state.bpm = bpm;
+            }
             SetOct(oct) => self.state.octave = oct,
             LoadSound(idx, path) => {
                 // TODO: keep settings from previous sampler?
                 let snd = sampler::load_file(&path)?;
-                let handle = self.collector.handle();
-                let sampler: Box<dyn Plugin + Send> = Box::new(Sampler::new(snd));
-                let sampler = basedrop::Owned::new(&handle, sampler);
-                let sampler_id = DeviceId::new();
-                self.params.insert(sampler_id, sampler.params());
-
-                let cmd = EngineCommand::CreateInstrument(sampler_id, sampler);
-                self.send_to_engine(cmd)?;
-
-                if let Some(instr) = &self.state.instruments[idx] {
-                    self.params.remove(&instr.id);
-                    self.send_to_engine(EngineCommand::DeleteInstrument(instr.id))?;
-                }
-
-                self.state.instruments[idx] = Some(Instrument {
-                    id: sampler_id,
-                    name: path.file_name().unwrap().to_string(),
-                });
+                self.set_instrument(idx, path, snd)?;
+            }
+            SaveProject(path) => {
+                project::write(&path, &self.project())?;
+                self.project_path = Some(path);
+            }
+            LoadProject(path) => {
+                let project = project::read(&path)?;
+                self.load_project(project)?;
+                self.history.clear();
+                self.project_path = Some(path);
+            }
+            // Rendering takes a while, so it's done in the background to keep the UI responsive
+            Render(path, options) => {
+                let project = self.project();
+                let dst = path.clone();
+                let handle = thread::spawn(move || render::render(project, &dst, &options));
+                self.renders.push((path, handle));
+            }
+            RenderStems(dir, options) => {
+                let project = self.project();
+                let dst = dir.clone();
+                let handle = thread::spawn(move || render::render_stems(project, &dst, &options));
+                self.renders.push((dir, handle));
             }
             LoopToggle(idx) => {
                 self.state.loop_range = match self.state.loop_range {
@@ -161,14 +262,11 @@ impl App {
             CreatePattern(idx) => {
                 if self.state.patterns.len() < MAX_PATTERNS {
                     let id = self.next_pattern_id();
-                    let num_instruments = self
-                        .state
-                        .tracks
-                        .iter()
-                        .filter(|track| matches!(track.track_type, TrackType::Instrument))
-                        .count();
-
-                    let pattern = Pattern::new(num_instruments);
+                    let mut pattern = Pattern::new(self.num_instrument_tracks());
+                    // All patterns share the same note columns
+                    if let Some(other) = self.state.patterns.values().next() {
+                        pattern.copy_layout(other);
+                    }
                     self.state.patterns.insert(id, Arc::new(pattern));
                     if let Some(idx) = idx {
                         self.state.song.insert(idx + 1, id);
@@ -192,14 +290,193 @@ impl App {
             }
             ChangeDir(dir) => self.file_browser.move_to(dir)?,
             CreateTrack(idx) => {
-                let track = engine::Track::new();
-                let rms = track.rms_out.clone();
-                let track_info = Track::new(rms);
-                self.params.insert(track_info.device_id, track.params());
-
-                let cmd = EngineCommand::CreateTrack(track_info.id, Box::new(track));
-                self.send_to_engine(cmd)?;
-                self.state.tracks.insert(idx, track_info);
+                let num_tracks = self.num_instrument_tracks();
+                if num_tracks >= MAX_INSTRUMENT_TRACKS {
+                    return Err(anyhow!(
+                        "can't create more than {} tracks",
+                        MAX_INSTRUMENT_TRACKS
+                    ));
+                }
+                if idx > num_tracks {
+                    return Err(anyhow!("invalid track index {}", idx));
+                }
+                self.create_track(idx, TrackType::Instrument)?;
+                for pattern in self.state.patterns.values_mut() {
+                    Arc::make_mut(pattern).insert_track(idx);
+                }
+                // Undo history refers to patterns with a different number of tracks
+                self.history.clear();
+            }
+            DeleteTrack(idx) => {
+                let num_tracks = self.num_instrument_tracks();
+                if idx + 1 >= self.state.tracks.len() {
+                    return Err(anyhow!("can't delete the master track"));
+                }
+                let track_type = self.state.tracks[idx].track_type;
+                if matches!(track_type, TrackType::Instrument) && num_tracks == 1 {
+                    return Err(anyhow!("can't delete the last track"));
+                }
+                self.delete_track(idx)?;
+                if matches!(track_type, TrackType::Instrument) {
+                    for pattern in self.state.patterns.values_mut() {
+                        Arc::make_mut(pattern).remove_track(idx);
+                    }
+                    self.history.clear();
+                }
+            }
+            CreateInstrument(idx) => {
+                if self.state.instruments.len() >= MAX_INSTRUMENTS {
+                    return Err(anyhow!(
+                        "can't create more than {} instruments",
+                        MAX_INSTRUMENTS
+                    ));
+                }
+                if idx > self.state.instruments.len() {
+                    return Err(anyhow!("invalid instrument index {}", idx));
+                }
+                self.state.instruments.insert(idx, None);
+                if idx + 1 < self.state.instruments.len() {
+                    let idx = idx as u8;
+                    self.map_instruments(|i| Some(if i >= idx { i + 1 } else { i }));
+                }
+            }
+            DeleteInstrument(idx) => {
+                if self.state.instruments.len() == 1 {
+                    return Err(anyhow!("can't delete the last instrument"));
+                }
+                self.clear_instrument(idx)?;
+                self.state.instruments.remove(idx);
+                let idx = idx as u8;
+                self.map_instruments(|i| match i {
+                    i if i < idx => Some(i),
+                    i if i > idx => Some(i - 1),
+                    _ => None,
+                });
+            }
+            DuplicateInstrument(idx) => {
+                let Some(instr) = self.state.instruments[idx].clone() else {
+                    return Err(anyhow!("instrument {} is empty", idx));
+                };
+                let snd = sampler::load_file(&instr.path)?;
+                self.dispatch(CreateInstrument(idx + 1))?;
+                let id = self.set_instrument(idx + 1, instr.path, snd)?;
+                let params = project::param_data(self.params(instr.id));
+                project::apply_param_data(self.params(id), &params);
+                if let Some(copy) = &mut self.state.instruments[idx + 1] {
+                    copy.name = instr.name;
+                }
+            }
+            SetNoteColumns(track_idx, note_columns) => {
+                if track_idx >= self.num_instrument_tracks() {
+                    return Err(anyhow!("only instrument tracks have note columns"));
+                }
+                if !(1..=MAX_NOTE_COLUMNS).contains(&note_columns) {
+                    return Err(anyhow!(
+                        "tracks can have 1 to {} note columns",
+                        MAX_NOTE_COLUMNS
+                    ));
+                }
+                for pattern in self.state.patterns.values_mut() {
+                    Arc::make_mut(pattern).set_note_columns(track_idx, note_columns);
+                }
+            }
+            RenameInstrument(idx, name) => {
+                if let Some(instr) = &mut self.state.instruments[idx] {
+                    instr.name = if name.is_empty() {
+                        instr.path.file_name().unwrap().to_string()
+                    } else {
+                        name
+                    };
+                }
+            }
+            RenameTrack(idx, name) => {
+                self.state.tracks[idx].name = Some(name).filter(|name| !name.is_empty());
+            }
+            RenamePattern(idx, name) => {
+                let id = self.state.song[idx];
+                let pattern = self.state.patterns.get_mut(&id).unwrap();
+                Arc::make_mut(pattern).name = Some(name).filter(|name| !name.is_empty());
+            }
+            SetTrackColor(idx, color) => self.state.tracks[idx].color = color,
+            CreateBus => {
+                let num_buses = self.state.tracks.iter().filter(|t| t.is_bus()).count() - 1;
+                if num_buses >= MAX_BUSES {
+                    return Err(anyhow!("can't create more than {} buses", MAX_BUSES));
+                }
+                // Buses go after the instrument tracks, before the master track
+                let idx = self.state.tracks.len() - 1;
+                self.create_track(idx, TrackType::Bus)?;
+                self.state.tracks[idx].name = Some(format!("Bus {}", num_buses + 1));
+            }
+            SetOutput(track_idx, bus_id) => {
+                let track = &self.state.tracks[track_idx];
+                if let Some(bus_id) = bus_id {
+                    let bus_idx = self
+                        .state
+                        .track_idx(bus_id)
+                        .ok_or_else(|| anyhow!("unknown bus"))?;
+                    if !self.state.tracks[bus_idx].is_bus() || bus_idx == track_idx {
+                        return Err(anyhow!("tracks can only be routed to another bus"));
+                    }
+                    if self.state.routes_to(bus_id, track.id) {
+                        return Err(anyhow!("routing would create a feedback loop"));
+                    }
+                }
+                let master_id = self.state.tracks.last().unwrap().id;
+                self.state.tracks[track_idx].output = bus_id.filter(|id| *id != master_id);
+            }
+            SetSend(track_idx, send_idx, bus_id) => {
+                if self.state.tracks[track_idx].is_bus() {
+                    return Err(anyhow!("only instrument tracks have sends"));
+                }
+                if let Some(bus_id) = bus_id {
+                    let master_idx = self.state.tracks.len() - 1;
+                    let is_return = self
+                        .state
+                        .track_idx(bus_id)
+                        .is_some_and(|idx| self.state.tracks[idx].is_bus() && idx != master_idx);
+                    if !is_return {
+                        return Err(anyhow!("sends can only be routed to a bus"));
+                    }
+                }
+                self.state.tracks[track_idx].sends[send_idx] = bus_id;
+            }
+            Solo(track_idx, exclusive) => {
+                // The master track can't be soloed, it's never silenced
+                if track_idx == self.state.tracks.len() - 1 {
+                    return Ok(());
+                }
+                let solo = !self.state.tracks[track_idx].solo
+                    || (exclusive
+                        && self.state.tracks.iter().filter(|track| track.solo).count() > 1);
+                if exclusive {
+                    for track in &mut self.state.tracks {
+                        track.solo = false;
+                    }
+                }
+                self.state.tracks[track_idx].solo = solo;
+            }
+            ToggleSoloSafe(track_idx) => {
+                let track = &mut self.state.tracks[track_idx];
+                if !track.is_bus() {
+                    return Err(anyhow!("only buses can be solo-safe"));
+                }
+                track.solo_safe = !track.solo_safe;
+            }
+            SetMute(track_idxs, mute) => {
+                let value = if mute { 0.0 } else { 1.0 };
+                for idx in track_idxs {
+                    if let Some(track) = self.state.tracks.get(idx) {
+                        self.params(track.device_id)
+                            .get_param(TrackParams::MUTE)
+                            .set(value);
+                    }
+                }
+            }
+            ResetClipIndicators => {
+                for track in &self.state.tracks {
+                    track.meters.clipped.store(false, Ordering::Relaxed);
+                }
             }
             ParamInc(device_id, param_idx, step_size) => {
                 self.params(device_id).get_param(param_idx).incr(step_size);
@@ -210,11 +487,551 @@ impl App {
             ParamToggle(device_id, param_idx) => {
                 self.params(device_id).get_param(param_idx).toggle();
             }
+            AddEffect(track_idx, effect_type) => {
+                self.add_effect(track_idx, effect_type)?;
+            }
+            RemoveEffect(track_idx, device_idx) => {
+                let track = &mut self.state.tracks[track_idx];
+                if device_idx < track.effects.len() {
+                    let device = track.effects.remove(device_idx);
+                    let cmd = EngineCommand::RemoveEffect(track.id, device.id);
+                    self.params.remove(&device.id);
+                    self.send_to_engine(cmd)?;
+                }
+            }
+            MoveEffect(track_idx, device_idx, new_idx) => {
+                let track = &mut self.state.tracks[track_idx];
+                if device_idx < track.effects.len() && new_idx < track.effects.len() {
+                    let device = track.effects.remove(device_idx);
+                    let cmd = EngineCommand::MoveEffect(track.id, device.id, new_idx);
+                    track.effects.insert(new_idx, device);
+                    self.send_to_engine(cmd)?;
+                }
+            }
+            SetSidechain(track_idx, device_idx, key) => {
+                let track = &mut self.state.tracks[track_idx];
+                if let Some(device) = track.effects.get_mut(device_idx) {
+                    device.sidechain = key;
+                    let cmd = EngineCommand::SetSidechain(track.id, device.id, key);
+                    self.send_to_engine(cmd)?;
+                }
+            }
+            ToggleBypass(track_idx, device_idx) => {
+                let track = &mut self.state.tracks[track_idx];
+                if let Some(device) = track.effects.get_mut(device_idx) {
+                    device.bypass = !device.bypass;
+                    let cmd = EngineCommand::BypassEffect(track.id, device.id, device.bypass);
+                    self.send_to_engine(cmd)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the edit that restores the current state if the message is something that can be
+    /// undone.
+    fn edit(&self, msg: &Msg) -> Option<Edit> {
+        use Msg::*;
+        match msg {
+            UpdatePattern(..) | CreatePattern(..) | DeletePattern(..) | RepeatPattern(..)
+            | ClonePattern(..) | RenamePattern(..) | SetNoteColumns(..) | LoopAdd(..)
+            | LoopToggle(..) => Some(Edit::Song(SongState {
+                patterns: self.state.patterns.clone(),
+                song: self.state.song.clone(),
+                loop_range: self.state.loop_range,
+                selected_pattern: self.state.selected_pattern,
+            })),
+            ParamInc(device_id, idx, _) | ParamDec(device_id, idx, _) => {
+                Some(self.param_edit(*device_id, *idx))
+            }
+            ParamToggle(device_id, idx) => {
+                let value = self.params(*device_id).get_param(*idx).target();
+                Some(Edit::Toggle(*device_id, *idx, value))
+            }
+            SetMute(track_idxs, _) => {
+                let edits = track_idxs
+                    .iter()
+                    .filter_map(|idx| self.state.tracks.get(*idx))
+                    .map(|track| self.param_edit(track.device_id, TrackParams::MUTE))
+                    .collect();
+                Some(Edit::Group(edits))
+            }
+            _ => None,
+        }
+    }
+
+    fn param_edit(&self, device_id: DeviceId, idx: usize) -> Edit {
+        let value = self.params(device_id).get_param(idx).target();
+        Edit::Param(device_id, idx, value)
+    }
+
+    /// Whether reverting the edit would leave the state as it is
+    fn is_current(&self, edit: &Edit) -> bool {
+        match edit {
+            Edit::Song(song) => {
+                song.patterns == self.state.patterns
+                    && song.song == self.state.song
+                    && song.loop_range == self.state.loop_range
+            }
+            Edit::Param(device_id, idx, value) | Edit::Toggle(device_id, idx, value) => self
+                .params
+                .get(device_id)
+                .is_none_or(|params| params.get_param(*idx).target() == *value),
+            Edit::Group(edits) => edits.iter().all(|edit| self.is_current(edit)),
+        }
+    }
+
+    fn record(&mut self, edit: Edit) {
+        // Consecutive changes to the same parameter, e.g. volume nudges, are undone in one go
+        if let (Some(Edit::Param(id1, idx1, _)), Edit::Param(id2, idx2, _)) =
+            (self.history.last(), &edit)
+        {
+            if id1 == id2 && idx1 == idx2 {
+                self.history.clear_redo();
+                return;
+            }
         }
+        self.history.push(edit);
+    }
 
+    /// Restore the state described by the edit and return an edit that restores the state from
+    /// before this call.
+    fn revert(&mut self, edit: Edit) -> Edit {
+        match edit {
+            Edit::Song(song) => {
+                let state = &mut self.state;
+                let current = SongState {
+                    patterns: std::mem::replace(&mut state.patterns, song.patterns),
+                    song: std::mem::replace(&mut state.song, song.song),
+                    loop_range: std::mem::replace(&mut state.loop_range, song.loop_range),
+                    selected_pattern: std::mem::replace(
+                        &mut state.selected_pattern,
+                        song.selected_pattern,
+                    ),
+                };
+                Edit::Song(current)
+            }
+            Edit::Param(device_id, idx, value) => {
+                Edit::Param(device_id, idx, self.swap_param(device_id, idx, value))
+            }
+            Edit::Toggle(device_id, idx, value) => {
+                Edit::Toggle(device_id, idx, self.swap_param(device_id, idx, value))
+            }
+            Edit::Group(edits) => {
+                let edits = edits.into_iter().rev().map(|edit| self.revert(edit));
+                Edit::Group(edits.collect())
+            }
+        }
+    }
+
+    /// Set a parameter and return its previous value
+    fn swap_param(&self, device_id: DeviceId, idx: usize, value: f64) -> f64 {
+        // The device might have been removed since the edit was made
+        let Some(params) = self.params.get(&device_id) else {
+            return value;
+        };
+        let param = params.get_param(idx);
+        let current = param.target();
+        param.set(value);
+        current
+    }
+
+    fn set_instrument(&mut self, idx: usize, path: Utf8PathBuf, snd: Sound) -> Result<DeviceId> {
+        let handle = self.collector.handle();
+        let sampler: Box<dyn Plugin + Send> = Box::new(Sampler::new(snd));
+        let sampler = basedrop::Owned::new(&handle, sampler);
+        let sampler_id = DeviceId::new();
+        self.params.insert(sampler_id, sampler.params());
+
+        let cmd = EngineCommand::CreateInstrument(sampler_id, sampler);
+        self.send_to_engine(cmd)?;
+        self.clear_instrument(idx)?;
+
+        self.state.instruments[idx] = Some(Instrument {
+            id: sampler_id,
+            name: path.file_name().unwrap().to_string(),
+            path,
+        });
+        Ok(sampler_id)
+    }
+
+    fn create_track(&mut self, idx: usize, track_type: TrackType) -> Result<()> {
+        let handle = self.collector.handle();
+        let track = engine::Track::new();
+        let mut track_info = Track::new(track.meters.clone());
+        track_info.track_type = track_type;
+        self.params.insert(track_info.device_id, track.params());
+
+        let track = basedrop::Owned::new(&handle, track);
+        let cmd = EngineCommand::CreateTrack(track_info.id, track);
+        self.send_to_engine(cmd)?;
+        self.state.tracks.insert(idx, track_info);
         Ok(())
     }
 
+    fn delete_track(&mut self, idx: usize) -> Result<()> {
+        let track = self.state.tracks.remove(idx);
+        self.send_to_engine(EngineCommand::DeleteTrack(track.id))?;
+        self.params.remove(&track.device_id);
+        for device in &track.effects {
+            self.params.remove(&device.id);
+        }
+        // Anything that referred to the track falls back to the defaults
+        let mut cmds = Vec::new();
+        for other in &mut self.state.tracks {
+            if other.output == Some(track.id) {
+                other.output = None;
+            }
+            for send in &mut other.sends {
+                if *send == Some(track.id) {
+                    *send = None;
+                }
+            }
+            for device in &mut other.effects {
+                if device.sidechain == Some(track.id) {
+                    device.sidechain = None;
+                    cmds.push(EngineCommand::SetSidechain(other.id, device.id, None));
+                }
+            }
+        }
+        for cmd in cmds {
+            self.send_to_engine(cmd)?;
+        }
+        Ok(())
+    }
+
+    fn add_effect(&mut self, track_idx: usize, effect_type: EffectType) -> Result<DeviceId> {
+        let track = &self.state.tracks[track_idx];
+        if track.effects.len() >= MAX_EFFECTS {
+            return Err(anyhow!(
+                "track can't have more than {} effects",
+                MAX_EFFECTS
+            ));
+        }
+        let handle = self.collector.handle();
+        let effect: Box<dyn Effect + Send> = effect_type.create();
+        let effect = basedrop::Owned::new(&handle, effect);
+        let device_id = DeviceId::new();
+        self.params.insert(device_id, effect.params());
+        let gain_reduction = effect.gain_reduction();
+
+        let idx = track.effects.len();
+        let cmd = EngineCommand::AddEffect(track.id, idx, device_id, effect);
+        self.send_to_engine(cmd)?;
+        self.state.tracks[track_idx].effects.push(Device {
+            id: device_id,
+            name: effect_type.name().to_string(),
+            effect_type,
+            bypass: false,
+            sidechain: None,
+            gain_reduction,
+        });
+        Ok(device_id)
+    }
+
+    /// Update the instrument numbers in all patterns after the instrument list changed
+    fn map_instruments(&mut self, f: impl Fn(u8) -> Option<u8>) {
+        for pattern in self.state.patterns.values_mut() {
+            Arc::make_mut(pattern).map_instruments(&f);
+        }
+        // Undo history refers to the old instrument numbers
+        self.history.clear();
+    }
+
+    fn clear_instrument(&mut self, idx: usize) -> Result<()> {
+        if let Some(instr) = self.state.instruments[idx].take() {
+            self.params.remove(&instr.id);
+            self.send_to_engine(EngineCommand::DeleteInstrument(instr.id))?;
+        }
+        Ok(())
+    }
+
+    fn project(&self) -> Project {
+        let mut patterns: Vec<PatternData> = self
+            .state
+            .patterns
+            .iter()
+            .map(|(id, pattern)| PatternData::new(id.0, pattern))
+            .collect();
+        patterns.sort_by_key(|p| p.id);
+        let instruments = self
+            .state
+            .instruments
+            .iter()
+            .map(|instr| {
+                instr.as_ref().map(|instr| InstrumentData {
+                    name: Some(instr.name.clone()),
+                    path: instr.path.clone(),
+                    params: project::param_data(self.params(instr.id)),
+                })
+            })
+            .collect();
+        let tracks = self
+            .state
+            .tracks
+            .iter()
+            .map(|track| TrackData {
+                name: track.name.clone(),
+                color: track.color.map(|color| match color {
+                    Color::Rgb(r, g, b) => [r, g, b],
+                    _ => [255, 255, 255],
+                }),
+                bus: track.is_bus(),
+                solo_safe: track.solo_safe,
+                output: track.output.and_then(|id| self.state.track_idx(id)),
+                sends: track
+                    .sends
+                    .map(|send| send.and_then(|id| self.state.track_idx(id))),
+                params: project::param_data(self.params(track.device_id)),
+                effects: track
+                    .effects
+                    .iter()
+                    .map(|device| EffectData {
+                        name: device.effect_type.name().to_string(),
+                        bypass: device.bypass,
+                        sidechain: device.sidechain.and_then(|id| self.state.track_idx(id)),
+                        params: project::param_data(self.params(device.id)),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Project {
+            version: project::VERSION,
+            bpm: self.state.bpm,
+            lines_per_beat: self.state.lines_per_beat,
+            song: self.state.song.iter().map(|id| id.0).collect(),
+            loop_range: self.state.loop_range,
+            patterns,
+            instruments,
+            tracks,
+        }
+    }
+
+    pub fn load_project(&mut self, project: Project) -> Result<()> {
+        // Validate the project and load all sounds before touching any state, so a broken
+        // project file leaves the current project intact.
+        validate_tempo(project.bpm, project.lines_per_beat)?;
+        // Instrument tracks come first, followed by the buses and the master track
+        let num_tracks = project.tracks.iter().take_while(|t| !t.bus).count();
+        if num_tracks == 0
+            || num_tracks == project.tracks.len()
+            || project.tracks[num_tracks..].iter().any(|t| !t.bus)
+        {
+            return Err(anyhow!("project tracks are invalid"));
+        }
+        if num_tracks > MAX_INSTRUMENT_TRACKS {
+            return Err(anyhow!(
+                "project has {} tracks, max is {}",
+                num_tracks,
+                MAX_INSTRUMENT_TRACKS
+            ));
+        }
+        let num_buses = project.tracks.len() - num_tracks - 1;
+        if num_buses > MAX_BUSES {
+            return Err(anyhow!(
+                "project has {} buses, max is {}",
+                num_buses,
+                MAX_BUSES
+            ));
+        }
+        let outputs: Vec<Option<usize>> = project.tracks.iter().map(|t| t.output).collect();
+        for (idx, output) in outputs.iter().enumerate() {
+            if let Some(output) = *output {
+                if output == idx || !project.tracks.get(output).is_some_and(|t| t.bus) {
+                    return Err(anyhow!("track {} is routed to an invalid bus", idx));
+                }
+            }
+        }
+        if has_routing_cycle(&outputs) {
+            return Err(anyhow!("project track routing contains a feedback loop"));
+        }
+        let master_idx = project.tracks.len() - 1;
+        for (idx, track) in project.tracks.iter().enumerate() {
+            for send in track.sends.iter().flatten() {
+                if track.bus
+                    || *send == master_idx
+                    || !project.tracks.get(*send).is_some_and(|t| t.bus)
+                {
+                    return Err(anyhow!("track {} has a send to an invalid bus", idx));
+                }
+            }
+        }
+        if project.instruments.len() > MAX_INSTRUMENTS {
+            return Err(anyhow!(
+                "project has {} instruments, max is {}",
+                project.instruments.len(),
+                MAX_INSTRUMENTS
+            ));
+        }
+        if project.song.is_empty() {
+            return Err(anyhow!("project has no patterns"));
+        }
+        for data in &project.tracks {
+            if data.effects.len() > MAX_EFFECTS {
+                return Err(anyhow!(
+                    "track can't have more than {} effects",
+                    MAX_EFFECTS
+                ));
+            }
+            if let Some(effect) = data
+                .effects
+                .iter()
+                .find(|effect| EffectType::from_name(&effect.name).is_none())
+            {
+                return Err(anyhow!("unknown effect {}", effect.name));
+            }
+        }
+
+        let mut patterns: HashMap<PatternId, Arc<Pattern>> = HashMap::new();
+        for data in &project.patterns {
+            if data.tracks.len() != num_tracks || data.len == 0 {
+                return Err(anyhow!("pattern {} has an invalid size", data.id));
+            }
+            let pattern = data.to_pattern()?;
+            // All patterns share the same note columns
+            if let Some(first) = patterns.values().next() {
+                if !pattern.has_layout_of(first) {
+                    return Err(anyhow!("pattern {} has different note columns", data.id));
+                }
+            }
+            patterns.insert(PatternId(data.id), Arc::new(pattern));
+        }
+        let song: Vec<PatternId> = project.song.iter().map(|id| PatternId(*id)).collect();
+        if let Some(id) = song.iter().find(|id| !patterns.contains_key(id)) {
+            return Err(anyhow!("song refers to unknown pattern {}", id));
+        }
+        if let Some((start, end)) = project.loop_range {
+            if start > end || end >= song.len() {
+                return Err(anyhow!("invalid loop range {}-{}", start, end));
+            }
+        }
+
+        let mut sounds = Vec::with_capacity(project.instruments.len());
+        for instr in &project.instruments {
+            let snd = match instr {
+                Some(instr) => Some(sampler::load_file(&instr.path)?),
+                None => None,
+            };
+            sounds.push(snd);
+        }
+
+        // Anything failing from here on (e.g. a full engine command queue) leaves the project
+        // partially loaded, so make sure it can't be saved over the project file or undone into.
+        if let Err(err) =
+            self.apply_project(&project, num_tracks, num_buses, patterns, song, sounds)
+        {
+            self.history.clear();
+            self.project_path = None;
+            return Err(err.context("project was only partially loaded"));
+        }
+        Ok(())
+    }
+
+    /// Replaces the current project with an already validated one
+    fn apply_project(
+        &mut self,
+        project: &Project,
+        num_tracks: usize,
+        num_buses: usize,
+        patterns: HashMap<PatternId, Arc<Pattern>>,
+        song: Vec<PatternId>,
+        sounds: Vec<Option<Sound>>,
+    ) -> Result<()> {
+        self.state.is_playing = false;
+        self.state.bpm = project.bpm;
+        self.state.lines_per_beat = project.lines_per_beat;
+        self.state.patterns = patterns;
+        self.state.song = song;
+        self.state.loop_range = project.loop_range;
+        self.state.selected_pattern = 0;
+
+        for idx in 0..self.state.instruments.len() {
+            self.clear_instrument(idx)?;
+        }
+        // Keep at least one slot to load sounds into
+        let num_instruments = project.instruments.len().max(1);
+        self.state.instruments.resize(num_instruments, None);
+        for (idx, (data, snd)) in project.instruments.iter().zip(sounds).enumerate() {
+            if let (Some(data), Some(snd)) = (data, snd) {
+                let id = self.set_instrument(idx, data.path.clone(), snd)?;
+                project::apply_param_data(self.params(id), &data.params);
+                if let (Some(instr), Some(name)) = (&mut self.state.instruments[idx], &data.name) {
+                    instr.name = name.clone();
+                }
+            }
+        }
+
+        // Match the number of instrument tracks, and replace all buses except the master track
+        while self.num_instrument_tracks() > num_tracks {
+            self.delete_track(self.num_instrument_tracks() - 1)?;
+        }
+        while self.num_instrument_tracks() < num_tracks {
+            self.create_track(self.num_instrument_tracks(), TrackType::Instrument)?;
+        }
+        while self.state.tracks.len() > num_tracks + 1 {
+            self.delete_track(num_tracks)?;
+        }
+        for _ in 0..num_buses {
+            self.create_track(self.state.tracks.len() - 1, TrackType::Bus)?;
+        }
+
+        for (track_idx, data) in project.tracks.iter().enumerate() {
+            let track = &mut self.state.tracks[track_idx];
+            track.name = data.name.clone();
+            track.color = data.color.map(|[r, g, b]| Color::Rgb(r, g, b));
+            let device_id = track.device_id;
+            project::apply_param_data(self.params(device_id), &data.params);
+
+            while !self.state.tracks[track_idx].effects.is_empty() {
+                self.dispatch(Msg::RemoveEffect(track_idx, 0))?;
+            }
+            for effect in &data.effects {
+                let effect_type = EffectType::from_name(&effect.name).unwrap();
+                let id = self.add_effect(track_idx, effect_type)?;
+                project::apply_param_data(self.params(id), &effect.params);
+                if effect.bypass {
+                    let device_idx = self.state.tracks[track_idx].effects.len() - 1;
+                    self.dispatch(Msg::ToggleBypass(track_idx, device_idx))?;
+                }
+            }
+        }
+
+        // Routing and sidechains can refer to any track, so they're set once all tracks are loaded
+        let master_idx = self.state.tracks.len() - 1;
+        for (track_idx, data) in project.tracks.iter().enumerate() {
+            self.state.tracks[track_idx].output = data
+                .output
+                .filter(|idx| *idx != master_idx)
+                .map(|idx| self.state.tracks[idx].id);
+            self.state.tracks[track_idx].sends = data
+                .sends
+                .map(|send| send.map(|idx| self.state.tracks[idx].id));
+            self.state.tracks[track_idx].solo = false;
+            self.state.tracks[track_idx].solo_safe = data.bus && data.solo_safe;
+            for (device_idx, effect) in data.effects.iter().enumerate() {
+                let key = effect
+                    .sidechain
+                    .and_then(|idx| self.state.tracks.get(idx))
+                    .map(|track| track.id);
+                if key.is_some() {
+                    self.dispatch(Msg::SetSidechain(track_idx, device_idx, key))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn num_instrument_tracks(&self) -> usize {
+        self.state
+            .tracks
+            .iter()
+            .filter(|track| matches!(track.track_type, TrackType::Instrument))
+            .count()
+    }
+
     pub fn params(&self, id: DeviceId) -> &Arc<dyn Params> {
         self.params.get(&id).unwrap()
     }
@@ -255,17 +1072,20 @@ impl App {
 pub struct EngineState {
     pub current_tick: usize,
     pub current_pattern: usize,
+    /// Loudness of the master output since playback was last started
+    pub loudness: Loudness,
 }
 
 impl EngineState {
     pub fn current_line(&self) -> usize {
-        self.current_tick / crate::engine::TICKS_PER_LINE
+        self.current_tick / TICKS_PER_LINE
     }
 }
 
 #[derive(Clone)]
 pub struct Instrument {
     pub name: String,
+    pub path: Utf8PathBuf,
     pub id: DeviceId,
 }
 
@@ -275,12 +1095,27 @@ pub struct AppState {
     pub bpm: u16,
     pub octave: u16,
     pub is_playing: bool,
+    /// Fill mode for conditional trigs
+    pub fill: bool,
     pub selected_pattern: usize,
     pub patterns: HashMap<PatternId, Arc<Pattern>>,
     pub song: Vec<PatternId>,
     pub loop_range: Option<(usize, usize)>,
     pub instruments: Vec<Option<Instrument>>,
     pub tracks: Vec<Track>,
+    /// Order in which the engine processes tracks, excluding the master track
+    pub track_order: Vec<TrackRoute>,
+}
+
+/// Buses that the engine mixes the output of a track into
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrackRoute {
+    pub id: TrackId,
+    /// Bus the track is routed to, or None for the master track
+    pub output: Option<TrackId>,
+    pub sends: [Option<TrackId>; NUM_SENDS],
+    pub solo: bool,
+    pub solo_safe: bool,
 }
 
 impl AppState {
@@ -300,6 +1135,24 @@ impl AppState {
         next
     }
 
+    pub fn track_idx(&self, id: TrackId) -> Option<usize> {
+        self.tracks.iter().position(|track| track.id == id)
+    }
+
+    /// Returns true if the output of track `from` ends up in track `to`
+    pub fn routes_to(&self, from: TrackId, to: TrackId) -> bool {
+        let mut current = Some(from);
+        // Routing can't contain cycles, but limit the number of steps anyway
+        for _ in 0..=self.tracks.len() {
+            match current {
+                Some(id) if id == to => return true,
+                Some(id) => current = self.track_idx(id).and_then(|idx| self.tracks[idx].output),
+                None => return false,
+            }
+        }
+        false
+    }
+
     pub fn selected_pattern(&self) -> &Pattern {
         let id = self.song[self.selected_pattern];
         self.patterns.get(&id).unwrap()
@@ -331,18 +1184,32 @@ pub struct Track {
     pub effects: Vec<Device>,
     pub track_type: TrackType,
     pub name: Option<String>,
-    pub rms: Arc<[AtomicF64; 2]>,
+    /// Bus that the track is routed to. Tracks without a bus are routed to the master track.
+    pub output: Option<TrackId>,
+    /// Return buses fed by the sends of an instrument track
+    pub sends: [Option<TrackId>; NUM_SENDS],
+    pub solo: bool,
+    /// Solo-safe buses are never silenced when other tracks are soloed
+    pub solo_safe: bool,
+    /// Color of the track header in the editor
+    pub color: Option<Color>,
+    pub meters: Arc<engine::Meters>,
 }
 
 impl Track {
-    fn new(rms: Arc<[AtomicF64; 2]>) -> Self {
+    fn new(meters: Arc<engine::Meters>) -> Self {
         Self {
             id: TrackId::new(),
             device_id: DeviceId::new(),
             effects: vec![],
             track_type: TrackType::Instrument,
             name: None,
-            rms,
+            output: None,
+            sends: [None; NUM_SENDS],
+            solo: false,
+            solo_safe: false,
+            color: None,
+            meters,
         }
     }
 
@@ -352,16 +1219,44 @@ impl Track {
 
     pub fn rms(&self) -> (f32, f32) {
         (
-            self.rms[0].load(Ordering::Relaxed) as f32,
-            self.rms[1].load(Ordering::Relaxed) as f32,
+            self.meters.rms[0].load(Ordering::Relaxed) as f32,
+            self.meters.rms[1].load(Ordering::Relaxed) as f32,
+        )
+    }
+
+    pub fn peak(&self) -> (f32, f32) {
+        (
+            self.meters.peak[0].load(Ordering::Relaxed) as f32,
+            self.meters.peak[1].load(Ordering::Relaxed) as f32,
         )
     }
+
+    pub fn clipped(&self) -> bool {
+        self.meters.clipped.load(Ordering::Relaxed)
+    }
+
+    /// Total gain reduction in dB of the dynamics effects on this track, or None if there
+    /// aren't any.
+    pub fn gain_reduction(&self) -> Option<f32> {
+        let mut devices = self
+            .effects
+            .iter()
+            .filter(|device| !device.bypass)
+            .filter_map(|device| device.gain_reduction.as_ref())
+            .peekable();
+        devices.peek()?;
+        Some(devices.map(|gr| gr.load(Ordering::Relaxed) as f32).sum())
+    }
 }
 
 #[derive(Clone)]
 pub struct Device {
     pub id: DeviceId,
     pub name: String,
+    pub effect_type: EffectType,
+    pub bypass: bool,
+    pub sidechain: Option<TrackId>,
+    pub gain_reduction: Option<Arc<AtomicF64>>,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -374,6 +1269,7 @@ pub fn new() -> Result<(App, Output<AppState>, Engine, Output<EngineState>)> {
     let engine_state = EngineState {
         current_pattern: 0,
         current_tick: 0,
+        loudness: Loudness::default(),
     };
 
     let mut app_state = AppState {
@@ -381,15 +1277,19 @@ pub fn new() -> Result<(App, Output<AppState>, Engine, Output<EngineState>)> {
         lines_per_beat: 4,
         octave: 4,
         is_playing: false,
+        fill: false,
         patterns: HashMap::new(),
         song: Vec::new(),
         selected_pattern: 0,
         loop_range: Some((0, 0)),
         instruments: vec![None; INSTRUMENT_TRACKS],
         tracks: Vec::new(),
+        track_order: Vec::new(),
     };
 
     let preview_track_id = TrackId::new();
+    let collector = basedrop::Collector::new();
+    let preview_track = basedrop::Owned::new(&collector.handle(), engine::Track::new());
 
     // Triple buffers are used to share app state with the engine and vice versa. This should
     // ensure that both threads always have a coherent view of the other thread's state.
@@ -400,21 +1300,24 @@ pub fn new() -> Result<(App, Output<AppState>, Engine, Output<EngineState>)> {
 
     // Create master track
     let master = engine::Track::new();
-    let rms = master.rms_out.clone();
-    let mut track = Track::new(rms);
+    let mut track = Track::new(master.meters.clone());
     device_params.insert(track.device_id, master.params());
 
     track.name = Some(String::from("Master"));
     track.track_type = TrackType::Bus;
+
+    let master_id = track.id;
     app_state.tracks.push(track);
 
-    let (producer, consumer) = RingBuffer::<EngineCommand>::new(64).split();
+    let (producer, consumer) = RingBuffer::<EngineCommand>::new(COMMAND_QUEUE_SIZE).split();
     let engine = Engine::new(
         engine_state,
         engine_state_input,
         consumer,
         master,
+        master_id,
         preview_track_id,
+        preview_track,
     );
 
     // We'll manage size manually so we can delete devices in the engine on eviction
@@ -428,8 +1331,11 @@ pub fn new() -> Result<(App, Output<AppState>, Engine, Output<EngineState>)> {
         params: device_params,
         preview_track_id,
         preview_cache,
-        collector: basedrop::Collector::new(),
+        collector,
         engine_state: EngineState::default(),
+        project_path: None,
+        history: History::new(),
+        renders: Vec::new(),
     };
     Ok((app, app_state_output, engine, engine_state_output))
 }
@@ -437,7 +1343,10 @@ pub fn new() -> Result<(App, Output<AppState>, Engine, Output<EngineState>)> {
 pub enum Msg {
     Noop,
     Exit,
+    Undo,
+    Redo,
     TogglePlay,
+    ToggleFill,
     LoadSound(usize, Utf8PathBuf),
     PreviewSound(Utf8PathBuf),
     LoopAdd(usize),
@@ -454,9 +1363,43 @@ pub enum Msg {
     SetBpm(u16),
     SetOct(u16),
     CreateTrack(usize),
+    CreateBus,
+    SetOutput(usize, Option<TrackId>),
+    SetSend(usize, usize, Option<TrackId>),
+    /// Toggle solo for a track. Exclusive solo unsolos all other tracks.
+    Solo(usize, bool),
+    ToggleSoloSafe(usize),
+    /// Mute or unmute several tracks at once
+    SetMute(Vec<usize>, bool),
+    /// Delete an instrument track or a bus
+    DeleteTrack(usize),
+    /// Insert an empty slot into the instrument list
+    CreateInstrument(usize),
+    DeleteInstrument(usize),
+    /// Insert a copy of an instrument right after it
+    DuplicateInstrument(usize),
+    /// Change the number of note columns of a track in all patterns
+    SetNoteColumns(usize, usize),
+    /// Rename an instrument, an empty name resets it to the file name
+    RenameInstrument(usize, String),
+    /// Rename a track, an empty name removes the name
+    RenameTrack(usize, String),
+    /// Rename the pattern at a position in the song
+    RenamePattern(usize, String),
+    SetTrackColor(usize, Option<Color>),
+    ResetClipIndicators,
     ParamInc(DeviceId, usize, StepSize),
     ParamDec(DeviceId, usize, StepSize),
     ParamToggle(DeviceId, usize),
+    AddEffect(usize, EffectType),
+    RemoveEffect(usize, usize),
+    MoveEffect(usize, usize, usize),
+    ToggleBypass(usize, usize),
+    SetSidechain(usize, usize, Option<TrackId>),
+    SaveProject(Utf8PathBuf),
+    LoadProject(Utf8PathBuf),
+    Render(Utf8PathBuf, RenderOptions),
+    RenderStems(Utf8PathBuf, RenderOptions),
 }
 
 impl Msg {
@@ -492,9 +1435,232 @@ impl TrackId {
     }
 }
 
+/// Returns the order in which tracks need to be processed, along with the buses they're routed
+/// to. Buses are processed after all tracks routed or sent to them, and tracks used as sidechain input
+/// are processed before the tracks that use them. Tracks are otherwise processed in the order
+/// they appear in. When tracks depend on each other through sidechains, one of them receives its
+/// input with a delay of one buffer. The master track is always the last track and is left out.
+fn processing_order(tracks: &[Track]) -> Vec<TrackRoute> {
+    fn visit(idx: usize, tracks: &[Track], visited: &mut [bool], order: &mut Vec<TrackRoute>) {
+        if visited[idx] {
+            return;
+        }
+        visited[idx] = true;
+        let track = &tracks[idx];
+        for key in track.effects.iter().filter_map(|device| device.sidechain) {
+            if let Some(key_idx) = tracks.iter().position(|track| track.id == key) {
+                visit(key_idx, tracks, visited, order);
+            }
+        }
+        if track.is_bus() {
+            for (input_idx, input) in tracks.iter().enumerate() {
+                if input.output == Some(track.id) || input.sends.contains(&Some(track.id)) {
+                    visit(input_idx, tracks, visited, order);
+                }
+            }
+        }
+        order.push(TrackRoute {
+            id: track.id,
+            output: track.output,
+            sends: track.sends,
+            solo: track.solo,
+            solo_safe: track.solo_safe,
+        });
+    }
+
+    let mut visited = vec![false; tracks.len()];
+    let mut order = Vec::with_capacity(tracks.len());
+    if let Some(master_idx) = tracks.len().checked_sub(1) {
+        visited[master_idx] = true;
+    }
+    for idx in 0..tracks.len() {
+        visit(idx, tracks, &mut visited, &mut order);
+    }
+    order
+}
+
+/// Checks if following the outputs of tracks, given by index, ever leads back to the same track
+fn has_routing_cycle(outputs: &[Option<usize>]) -> bool {
+    (0..outputs.len()).any(|start| {
+        let mut current = outputs[start];
+        for _ in 0..outputs.len() {
+            match current {
+                Some(idx) if idx == start => return true,
+                Some(idx) => current = outputs.get(idx).copied().flatten(),
+                None => return false,
+            }
+        }
+        current.is_some()
+    })
+}
+
+/// The engine computes the tick length from the product of the ticks per line, lines per beat
+/// and bpm, which has to fit in a `u16`
+fn validate_tempo(bpm: u16, lines_per_beat: u16) -> Result<()> {
+    let ticks_per_minute = (TICKS_PER_LINE as u16)
+        .checked_mul(lines_per_beat)
+        .and_then(|ticks| ticks.checked_mul(bpm));
+    match ticks_per_minute {
+        Some(ticks) if ticks > 0 => Ok(()),
+        _ => Err(anyhow!(
+            "invalid tempo: {} bpm with {} lines per beat",
+            bpm,
+            lines_per_beat
+        )),
+    }
+}
+
 pub fn random_color() -> Color {
     let r = rand::random::<u8>();
     let g = rand::random::<u8>();
     let b = rand::random::<u8>();
     Color::Rgb(r, g, b)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track_with_sidechain(key: Option<TrackId>) -> Track {
+        let mut track = Track::new(Arc::default());
+        track.effects.push(Device {
+            id: DeviceId::new(),
+            name: String::from("Ducker"),
+            effect_type: EffectType::Ducker,
+            bypass: false,
+            sidechain: key,
+            gain_reduction: None,
+        });
+        track
+    }
+
+    fn bus(output: Option<TrackId>) -> Track {
+        let mut track = track_with_sidechain(None);
+        track.track_type = TrackType::Bus;
+        track.output = output;
+        track
+    }
+
+    fn order(tracks: &[Track]) -> Vec<TrackId> {
+        processing_order(tracks)
+            .iter()
+            .map(|route| route.id)
+            .collect()
+    }
+
+    #[test]
+    fn sidechain_processing_order() {
+        let master = bus(None);
+        let kick = track_with_sidechain(None);
+        let bass = track_with_sidechain(Some(kick.id));
+        let pad = track_with_sidechain(Some(bass.id));
+        let tracks = vec![pad.clone(), bass.clone(), kick.clone(), master.clone()];
+        assert_eq!(vec![kick.id, bass.id, pad.id], order(&tracks));
+
+        // Tracks keyed from each other are still processed once
+        let mut a = track_with_sidechain(None);
+        let b = track_with_sidechain(Some(a.id));
+        a.effects[0].sidechain = Some(b.id);
+        let tracks = vec![a.clone(), b.clone(), master];
+        assert_eq!(vec![b.id, a.id], order(&tracks));
+    }
+
+    #[test]
+    fn bus_processing_order() {
+        let master = bus(None);
+        let group = bus(None);
+        let drums = bus(Some(group.id));
+        let mut kick = track_with_sidechain(None);
+        kick.output = Some(drums.id);
+        let mut bass = track_with_sidechain(None);
+        bass.output = Some(group.id);
+        let pad = track_with_sidechain(None);
+
+        let tracks = vec![
+            kick.clone(),
+            bass.clone(),
+            pad.clone(),
+            group.clone(),
+            drums.clone(),
+            master,
+        ];
+        let routes: Vec<_> = processing_order(&tracks)
+            .iter()
+            .map(|route| (route.id, route.output))
+            .collect();
+        assert_eq!(
+            vec![
+                (kick.id, Some(drums.id)),
+                (bass.id, Some(group.id)),
+                (pad.id, None),
+                (drums.id, Some(group.id)),
+                (group.id, None),
+            ],
+            routes
+        );
+    }
+
+    #[test]
+    fn return_processing_order() {
+        let master = bus(None);
+        let reverb = bus(None);
+        let mut lead = track_with_sidechain(None);
+        lead.sends[1] = Some(reverb.id);
+        let pad = track_with_sidechain(None);
+
+        // The return bus comes first, but is processed after the track sending to it
+        let tracks = vec![reverb.clone(), pad.clone(), lead.clone(), master];
+        assert_eq!(vec![lead.id, reverb.id, pad.id], order(&tracks));
+    }
+
+    #[test]
+    fn routing_cycles() {
+        assert!(!has_routing_cycle(&[Some(2), Some(2), None]));
+        assert!(has_routing_cycle(&[Some(1), Some(2), Some(1)]));
+    }
+
+    #[test]
+    fn tempo_validation() {
+        assert!(validate_tempo(120, 4).is_ok());
+        assert!(validate_tempo(0, 4).is_err());
+        assert!(validate_tempo(120, 0).is_err());
+        assert!(validate_tempo(999, 16).is_err());
+    }
+
+    #[test]
+    fn undo_mute() {
+        let (mut app, ..) = new().unwrap();
+        app.send(Msg::CreateTrack(0)).unwrap();
+        app.send(Msg::CreateTrack(0)).unwrap();
+        app.send(Msg::CreatePattern(None)).unwrap();
+        app.history.clear();
+        let mute = |app: &App, idx: usize| {
+            let id = app.state.tracks[idx].device_id;
+            app.params(id).get_param(TrackParams::MUTE).target()
+        };
+
+        app.send(Msg::SetMute(vec![0, 1], true)).unwrap();
+        assert_eq!((0.0, 0.0), (mute(&app, 0), mute(&app, 1)));
+        app.send(Msg::Undo).unwrap();
+        assert_eq!((1.0, 1.0), (mute(&app, 0), mute(&app, 1)));
+        app.send(Msg::Redo).unwrap();
+        assert_eq!((0.0, 0.0), (mute(&app, 0), mute(&app, 1)));
+
+        // Unmuting tracks that aren't muted doesn't change anything, so there's nothing to undo
+        app.send(Msg::Undo).unwrap();
+        app.send(Msg::SetMute(vec![0, 1], false)).unwrap();
+        assert!(app.history.last().is_none());
+        let msg = app.update_pattern(|_| {});
+        app.send(msg).unwrap();
+        assert!(app.history.last().is_none());
+
+        // Toggling twice is undone one toggle at a time
+        let id = app.state.tracks[0].device_id;
+        app.send(Msg::ParamToggle(id, TrackParams::MUTE)).unwrap();
+        app.send(Msg::ParamToggle(id, TrackParams::MUTE)).unwrap();
+        app.send(Msg::Undo).unwrap();
+        assert_eq!(0.0, mute(&app, 0));
+        app.send(Msg::Undo).unwrap();
+        assert_eq!(1.0, mute(&app, 0));
+    }
+}
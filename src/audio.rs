@@ -99,6 +99,24 @@ impl<const N: usize> Div<f32> for Frame<N> {
 
 pub type Stereo = Frame<2>;
 
+impl Stereo {
+    /// Equal-power panning, where -1 is hard left and 1 is hard right. The gain is normalized so
+    /// centered frames are unchanged.
+    pub fn pan(self, pan: f32) -> Self {
+        let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+        let gain = std::f32::consts::SQRT_2;
+        self * Frame::new([angle.cos() * gain, angle.sin() * gain])
+    }
+
+    /// Scales the side signal, so 0 is mono, 1 leaves the frame unchanged and values above 1
+    /// widen the stereo image.
+    pub fn with_width(self, width: f32) -> Self {
+        let mid = (self.0[0] + self.0[1]) * 0.5;
+        let side = (self.0[0] - self.0[1]) * 0.5 * width;
+        Frame::new([mid + side, mid - side])
+    }
+}
+
 pub type Buffer = Vec<Stereo>;
 
 // TODO: consider recalculating the sum every so often to prevent floating point
@@ -138,6 +156,45 @@ impl Rms {
     }
 }
 
+/// Per-channel peak level. The highest sample is held for a number of frames, after which the
+/// level decays until a higher sample comes along.
+pub struct Peak {
+    value: [f32; 2],
+    held: [usize; 2],
+    hold_frames: usize,
+    decay: f32,
+}
+
+impl Peak {
+    /// `decay` is the factor the level is multiplied with for every frame after the hold time
+    pub fn new(hold_frames: usize, decay: f32) -> Self {
+        Self {
+            value: [0.0; 2],
+            held: [0; 2],
+            hold_frames,
+            decay,
+        }
+    }
+
+    pub fn add_frame(&mut self, frame: Stereo) {
+        for ch in 0..2 {
+            let sample = frame.channel(ch).abs();
+            if sample >= self.value[ch] {
+                self.value[ch] = sample;
+                self.held[ch] = self.hold_frames;
+            } else if self.held[ch] > 0 {
+                self.held[ch] -= 1;
+            } else {
+                self.value[ch] *= self.decay;
+            }
+        }
+    }
+
+    pub fn value(&self) -> Stereo {
+        Frame::new(self.value)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -180,6 +237,36 @@ mod tests {
         assert_eq!(b, frame![0.25, 0.1]);
     }
 
+    #[test]
+    fn pan_and_width() {
+        let centered = frame![0.5, 0.5].pan(0.0);
+        assert!((centered.channel(0) - 0.5).abs() < 1e-6);
+        assert!((centered.channel(1) - 0.5).abs() < 1e-6);
+
+        let left = frame![0.5, 0.5].pan(-1.0);
+        assert!((left.channel(0) - 0.5 * std::f32::consts::SQRT_2).abs() < 1e-6);
+        assert!(left.channel(1).abs() < 1e-6);
+
+        assert_eq!(frame![0.5, 0.5], frame![0.75, 0.25].with_width(0.0));
+        assert_eq!(frame![0.75, 0.25], frame![0.75, 0.25].with_width(1.0));
+        assert_eq!(frame![1.0, 0.0], frame![0.75, 0.25].with_width(2.0));
+    }
+
+    #[test]
+    fn peak_hold_and_decay() {
+        let mut peak = Peak::new(2, 0.5);
+        for frame in [frame![0.8, -0.4], frame![0.1, 0.1]] {
+            peak.add_frame(frame);
+        }
+        assert_eq!(frame![0.8, 0.4], peak.value());
+        peak.add_frame(frame![0.1, 0.1]);
+        assert_eq!(frame![0.8, 0.4], peak.value());
+        peak.add_frame(frame![0.1, 0.1]);
+        assert_eq!(frame![0.4, 0.2], peak.value());
+        peak.add_frame(frame![-0.9, 0.1]);
+        assert_eq!(frame![0.9, 0.1], peak.value());
+    }
+
     #[test]
     fn rms() {
         let mut rms = Rms::new(8);
@@ -0,0 +1,73 @@
+pub mod compressor;
+pub mod delay;
+pub mod ducker;
+pub mod filter;
+pub mod gain;
+pub mod limiter;
+pub mod reverb;
+
+use crate::engine::Effect;
+
+use compressor::Compressor;
+use delay::Delay;
+use ducker::Ducker;
+use filter::Filter;
+use gain::Gain;
+use limiter::Limiter;
+use reverb::Reverb;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EffectType {
+    Gain,
+    Filter,
+    Delay,
+    Reverb,
+    Compressor,
+    Limiter,
+    Ducker,
+}
+
+impl EffectType {
+    pub const ALL: [EffectType; 7] = [
+        EffectType::Gain,
+        EffectType::Filter,
+        EffectType::Delay,
+        EffectType::Reverb,
+        EffectType::Compressor,
+        EffectType::Limiter,
+        EffectType::Ducker,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EffectType::Gain => "Gain",
+            EffectType::Filter => "Filter",
+            EffectType::Delay => "Delay",
+            EffectType::Reverb => "Reverb",
+            EffectType::Compressor => "Compressor",
+            EffectType::Limiter => "Limiter",
+            EffectType::Ducker => "Ducker",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<EffectType> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+
+    /// Returns true for effects that take input from another track
+    pub fn has_sidechain(&self) -> bool {
+        matches!(self, EffectType::Ducker)
+    }
+
+    pub fn create(&self) -> Box<dyn Effect + Send> {
+        match self {
+            EffectType::Gain => Box::new(Gain::new()),
+            EffectType::Filter => Box::new(Filter::new()),
+            EffectType::Delay => Box::new(Delay::new()),
+            EffectType::Reverb => Box::new(Reverb::new()),
+            EffectType::Compressor => Box::new(Compressor::new()),
+            EffectType::Limiter => Box::new(Limiter::new()),
+            EffectType::Ducker => Box::new(Ducker::new()),
+        }
+    }
+}
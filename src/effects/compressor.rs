@@ -0,0 +1,171 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use atomic_float::AtomicF64;
+
+use crate::audio::Stereo;
+use crate::engine::{Effect, EffectContext, ProcessStatus};
+use crate::params::{self, format_millis, Param, ParamInfo, Params};
+use crate::SAMPLE_RATE;
+use param_derive::Params;
+
+#[derive(Params)]
+pub struct CompressorParams {
+    threshold: Param,
+    ratio: Param,
+    attack: Param,
+    release: Param,
+    knee: Param,
+    makeup: Param,
+}
+
+impl Default for CompressorParams {
+    fn default() -> Self {
+        Self {
+            threshold: Param::new(
+                -12.0,
+                ParamInfo::new("Threshold", -60, 0).with_steps([0.5, 3.0]),
+            ),
+            ratio: Param::new(
+                4.0,
+                ParamInfo::new("Ratio", 1, 20)
+                    .with_steps([0.1, 1.0])
+                    .with_formatter(|v| format!("{:.1}:1", v)),
+            ),
+            attack: Param::new(
+                10.0,
+                ParamInfo::new("Attack", 0.1, 200.0)
+                    .with_steps([0.1, 5.0])
+                    .with_formatter(format_millis),
+            ),
+            release: Param::new(
+                100.0,
+                ParamInfo::new("Release", 5, 2000)
+                    .with_steps([5, 50])
+                    .with_formatter(format_millis),
+            ),
+            knee: Param::new(6.0, ParamInfo::new("Knee", 0, 24).with_steps([0.5, 3.0])),
+            makeup: Param::new(
+                0.0,
+                ParamInfo::new("Makeup", 0, 24)
+                    .with_steps([0.25, 1.0])
+                    .with_smoothing(params::ExpSmoothing::default()),
+            ),
+        }
+    }
+}
+
+/// Coefficient for a one pole filter that reaches ~63% of its target after `ms` milliseconds
+pub fn time_constant(ms: f64) -> f32 {
+    (-1.0 / (ms * SAMPLE_RATE / 1000.0)).exp() as f32
+}
+
+/// Feed forward compressor with a soft knee. The level of both channels is detected together so
+/// the stereo image doesn't shift when compressing.
+pub struct Compressor {
+    params: Arc<CompressorParams>,
+    /// Smoothed gain reduction in dB
+    envelope: f32,
+    gain_reduction: Arc<AtomicF64>,
+}
+
+impl Compressor {
+    pub fn new() -> Self {
+        Self {
+            params: Arc::new(CompressorParams::default()),
+            envelope: 0.0,
+            gain_reduction: Arc::new(AtomicF64::new(0.0)),
+        }
+    }
+
+    /// Returns the static gain reduction in dB for an input level in dB
+    fn gain_computer(&self, level: f32) -> f32 {
+        let threshold = self.params.threshold.value() as f32;
+        let ratio = self.params.ratio.value() as f32;
+        let knee = self.params.knee.value() as f32;
+
+        let over = level - threshold;
+        let output = if 2.0 * over < -knee {
+            level
+        } else if knee > 0.0 && 2.0 * over.abs() <= knee {
+            level + (1.0 / ratio - 1.0) * (over + knee / 2.0).powi(2) / (2.0 * knee)
+        } else {
+            threshold + over / ratio
+        };
+        level - output
+    }
+}
+
+impl Effect for Compressor {
+    fn process(&mut self, _ctx: &EffectContext, buf: &mut [Stereo]) -> ProcessStatus {
+        let attack = time_constant(self.params.attack.value());
+        let release = time_constant(self.params.release.value());
+
+        for frame in buf.iter_mut() {
+            let peak = frame.channel(0).abs().max(frame.channel(1).abs());
+            let level = 20.0 * peak.max(1e-6).log10();
+            let target = self.gain_computer(level);
+            let coef = if target > self.envelope {
+                attack
+            } else {
+                release
+            };
+            self.envelope = coef * self.envelope + (1.0 - coef) * target;
+
+            let makeup = self.params.makeup.value() as f32;
+            let gain = params::db_to_amp((makeup - self.envelope) as f64) as f32;
+            *frame = *frame * gain;
+        }
+        self.gain_reduction
+            .store(self.envelope as f64, Ordering::Relaxed);
+
+        ProcessStatus::Idle
+    }
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+
+    fn gain_reduction(&self) -> Option<Arc<AtomicF64>> {
+        Some(self.gain_reduction.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steady_state_gain_reduction() {
+        let mut compressor = Compressor::new();
+        compressor.params.threshold.set(-20.0);
+        compressor.params.ratio.set(4.0);
+        compressor.params.knee.set(0.0);
+        let ctx = EffectContext::new(120, 4);
+
+        // A constant -8dB signal is 12dB over the threshold, which should be reduced to 3dB
+        let amp = params::db_to_amp(-8.0) as f32;
+        let mut buf = vec![Stereo::new([amp, -amp]); SAMPLE_RATE as usize];
+        compressor.process(&ctx, &mut buf);
+
+        let out = buf.last().unwrap().channel(0);
+        let out_db = 20.0 * out.log10();
+        assert!((out_db - -17.0).abs() < 0.1, "output level {}", out_db);
+        let gr = compressor.gain_reduction.load(Ordering::Relaxed);
+        assert!((gr - 9.0).abs() < 0.1, "gain reduction {}", gr);
+    }
+
+    #[test]
+    fn hard_knee_at_threshold() {
+        let mut compressor = Compressor::new();
+        compressor.params.threshold.set(0.0);
+        compressor.params.knee.set(0.0);
+        assert_eq!(0.0, compressor.gain_computer(0.0));
+
+        // A full scale signal right at the threshold passes unchanged
+        let ctx = EffectContext::new(120, 4);
+        let mut buf = vec![Stereo::new([1.0, -1.0]); 64];
+        compressor.process(&ctx, &mut buf);
+        assert_eq!(Stereo::new([1.0, -1.0]), buf[63]);
+    }
+}
@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use crate::audio::Stereo;
+use crate::engine::{Effect, EffectContext, ProcessStatus};
+use crate::params::{self, format_millis, Param, ParamInfo, Params};
+use crate::SAMPLE_RATE;
+use param_derive::Params;
+
+const MAX_DELAY_SECONDS: usize = 5;
+// Controls how fast the delay time follows changes in the time or tempo, which results in a
+// tape-like pitch shift instead of clicks.
+const TIME_SMOOTHING: f32 = 0.0005;
+
+#[derive(Params)]
+pub struct DelayParams {
+    sync: Param,
+    time: Param,
+    lines: Param,
+    feedback: Param,
+    ping_pong: Param,
+    damping: Param,
+    mix: Param,
+}
+
+impl Default for DelayParams {
+    fn default() -> Self {
+        Self {
+            sync: Param::new(1.0, ParamInfo::bool("Sync", 1.0)),
+            time: Param::new(
+                250.0,
+                ParamInfo::new("Time", 1, MAX_DELAY_SECONDS as i32 * 1000)
+                    .with_steps([1, 10])
+                    .with_formatter(format_millis),
+            ),
+            lines: Param::new(
+                3.0,
+                ParamInfo::new("Time (lines)", 1, 64)
+                    .with_steps([1, 4])
+                    .with_formatter(|v| format!("{} lines", v)),
+            ),
+            feedback: Param::new(
+                0.4,
+                ParamInfo::new("Feedback", 0.0, 0.95)
+                    .with_smoothing(params::ExpSmoothing::default()),
+            ),
+            ping_pong: Param::new(0.0, ParamInfo::bool("Ping-pong", 1.0)),
+            damping: Param::new(
+                0.3,
+                ParamInfo::new("Damping", 0.0, 0.95)
+                    .with_smoothing(params::ExpSmoothing::default()),
+            ),
+            mix: Param::new(
+                0.3,
+                ParamInfo::new("Mix", 0.0, 1.0).with_smoothing(params::ExpSmoothing::default()),
+            ),
+        }
+    }
+}
+
+/// Stereo delay with a low-pass filter in the feedback path. The delay time is either set in
+/// milliseconds or in lines, in which case it follows the tempo of the song.
+pub struct Delay {
+    params: Arc<DelayParams>,
+    buf: Vec<Stereo>,
+    pos: usize,
+    /// Current delay time in samples, smoothed towards the time set by the params
+    delay: f32,
+    damping: Stereo,
+    /// Number of consecutive frames written to the delay line that were silent
+    silent_frames: usize,
+}
+
+impl Delay {
+    pub fn new() -> Self {
+        Self {
+            params: Arc::new(DelayParams::default()),
+            buf: vec![Stereo::ZERO; MAX_DELAY_SECONDS * SAMPLE_RATE as usize],
+            pos: 0,
+            delay: -1.0,
+            damping: Stereo::ZERO,
+            silent_frames: 0,
+        }
+    }
+
+    fn target_delay(&self, ctx: &EffectContext) -> f32 {
+        let samples = if self.params.sync.as_bool() {
+            self.params.lines.value() * ctx.samples_per_line()
+        } else {
+            self.params.time.value() * SAMPLE_RATE / 1000.0
+        };
+        (samples as f32).clamp(1.0, (self.buf.len() - 2) as f32)
+    }
+
+    /// Read from the delay line with linear interpolation
+    fn read(&self, delay: f32) -> Stereo {
+        let len = self.buf.len();
+        let offset = delay.floor();
+        let frac = delay - offset;
+        let i1 = (self.pos + len - offset as usize) % len;
+        let i2 = (i1 + len - 1) % len;
+        self.buf[i1] * (1.0 - frac) + self.buf[i2] * frac
+    }
+}
+
+impl Effect for Delay {
+    fn process(&mut self, ctx: &EffectContext, buf: &mut [Stereo]) -> ProcessStatus {
+        let target = self.target_delay(ctx);
+        if self.delay < 0.0 {
+            self.delay = target;
+        }
+        let ping_pong = self.params.ping_pong.as_bool();
+
+        for frame in buf.iter_mut() {
+            self.delay += (target - self.delay) * TIME_SMOOTHING;
+            let feedback = self.params.feedback.value() as f32;
+            let damping = self.params.damping.value() as f32;
+            let mix = self.params.mix.value() as f32;
+
+            let wet = self.read(self.delay);
+            self.damping = wet * (1.0 - damping) + self.damping * damping;
+            let fb = self.damping * feedback;
+
+            let input = *frame;
+            let write = if ping_pong {
+                // Feed the mono input into the left channel and let the repeats bounce between
+                // the channels
+                let mono = (input.channel(0) + input.channel(1)) * 0.5;
+                Stereo::new([mono + fb.channel(1), fb.channel(0)])
+            } else {
+                input + fb
+            };
+            self.buf[self.pos] = write;
+            self.pos = (self.pos + 1) % self.buf.len();
+
+            if write.channel(0).abs() < 1e-5 && write.channel(1).abs() < 1e-5 {
+                self.silent_frames += 1;
+            } else {
+                self.silent_frames = 0;
+            }
+
+            *frame = input * (1.0 - mix) + wet * mix;
+        }
+
+        if self.silent_frames > self.delay as usize {
+            ProcessStatus::Idle
+        } else {
+            ProcessStatus::Continue
+        }
+    }
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synced_echo() {
+        let mut delay = Delay::new();
+        delay.params.mix.set(1.0);
+        delay.params.feedback.set(0.5);
+        delay.params.damping.set(0.0);
+        delay.params.lines.set(2.0);
+
+        let ctx = EffectContext::new(120, 4);
+        // Let the smoothed parameters settle
+        delay.process(&ctx, &mut [Stereo::ZERO; 1024]);
+
+        // 2 lines at 120 bpm and 4 lines per beat is a quarter of a second
+        let delay_frames = SAMPLE_RATE as usize / 4;
+        let mut buf = vec![Stereo::ZERO; delay_frames * 3];
+        buf[0] = Stereo::new([1.0, 1.0]);
+        delay.process(&ctx, &mut buf);
+
+        let peaks: Vec<usize> = buf
+            .iter()
+            .enumerate()
+            .filter(|(_, frame)| frame.channel(0).abs() > 0.1)
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(vec![delay_frames, 2 * delay_frames], peaks);
+        assert_eq!(1.0, buf[delay_frames].channel(0));
+        assert_eq!(0.5, buf[2 * delay_frames].channel(0));
+    }
+}
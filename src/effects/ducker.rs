@@ -0,0 +1,134 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use atomic_float::AtomicF64;
+
+use crate::audio::Stereo;
+use crate::effects::compressor::time_constant;
+use crate::engine::{Effect, EffectContext, ProcessStatus};
+use crate::params::{self, format_millis, Param, ParamInfo, Params};
+use param_derive::Params;
+
+#[derive(Params)]
+pub struct DuckerParams {
+    threshold: Param,
+    ratio: Param,
+    depth: Param,
+    attack: Param,
+    release: Param,
+}
+
+impl Default for DuckerParams {
+    fn default() -> Self {
+        Self {
+            threshold: Param::new(
+                -24.0,
+                ParamInfo::new("Threshold", -60, 0).with_steps([0.5, 3.0]),
+            ),
+            ratio: Param::new(
+                8.0,
+                ParamInfo::new("Ratio", 1, 20)
+                    .with_steps([0.1, 1.0])
+                    .with_formatter(|v| format!("{:.1}:1", v)),
+            ),
+            depth: Param::new(24.0, ParamInfo::new("Depth", 0, 60).with_steps([0.5, 3.0])),
+            attack: Param::new(
+                1.0,
+                ParamInfo::new("Attack", 0.1, 200.0)
+                    .with_steps([0.1, 5.0])
+                    .with_formatter(format_millis),
+            ),
+            release: Param::new(
+                150.0,
+                ParamInfo::new("Release", 5, 2000)
+                    .with_steps([5, 50])
+                    .with_formatter(format_millis),
+            ),
+        }
+    }
+}
+
+/// Compressor that reduces the level of a track based on the level of the sidechain input,
+/// e.g. to duck a bass line whenever the kick drum plays. The gain reduction is limited to the
+/// depth parameter. Without sidechain input the signal passes through unchanged.
+pub struct Ducker {
+    params: Arc<DuckerParams>,
+    /// Smoothed gain reduction in dB
+    envelope: f32,
+    gain_reduction: Arc<AtomicF64>,
+}
+
+impl Ducker {
+    pub fn new() -> Self {
+        Self {
+            params: Arc::new(DuckerParams::default()),
+            envelope: 0.0,
+            gain_reduction: Arc::new(AtomicF64::new(0.0)),
+        }
+    }
+}
+
+impl Effect for Ducker {
+    fn process(&mut self, ctx: &EffectContext, buf: &mut [Stereo]) -> ProcessStatus {
+        let threshold = self.params.threshold.value() as f32;
+        let ratio = self.params.ratio.value() as f32;
+        let depth = self.params.depth.value() as f32;
+        let attack = time_constant(self.params.attack.value());
+        let release = time_constant(self.params.release.value());
+
+        for (i, frame) in buf.iter_mut().enumerate() {
+            let key = ctx.sidechain.map_or(Stereo::ZERO, |key| key[i]);
+            let peak = key.channel(0).abs().max(key.channel(1).abs());
+            let level = 20.0 * peak.max(1e-6).log10();
+            let target = if level > threshold {
+                f32::min(depth, (level - threshold) * (1.0 - 1.0 / ratio))
+            } else {
+                0.0
+            };
+            let coef = if target > self.envelope {
+                attack
+            } else {
+                release
+            };
+            self.envelope = coef * self.envelope + (1.0 - coef) * target;
+            *frame = *frame * params::db_to_amp(-self.envelope as f64) as f32;
+        }
+        self.gain_reduction
+            .store(self.envelope as f64, Ordering::Relaxed);
+
+        ProcessStatus::Idle
+    }
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+
+    fn gain_reduction(&self) -> Option<Arc<AtomicF64>> {
+        Some(self.gain_reduction.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ducks_on_sidechain_input() {
+        let mut ducker = Ducker::new();
+        ducker.params.depth.set(12.0);
+        let key = vec![Stereo::new([1.0, 1.0]); 4096];
+
+        let mut buf = vec![Stereo::new([0.5, 0.5]); 4096];
+        ducker.process(&EffectContext::new(120, 4), &mut buf);
+        assert_eq!(0.5, buf.last().unwrap().channel(0));
+
+        let ctx = EffectContext {
+            sidechain: Some(&key),
+            ..EffectContext::new(120, 4)
+        };
+        ducker.process(&ctx, &mut buf);
+        // A full scale key signal is way over the threshold, so we're limited by the depth
+        let out = buf.last().unwrap().channel(0);
+        assert!((20.0 * (out / 0.5).log10() - -12.0).abs() < 0.1);
+    }
+}
@@ -0,0 +1,200 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::audio::Stereo;
+use crate::engine::{Effect, EffectContext, ProcessStatus};
+use crate::params::{self, Param, ParamInfo, Params};
+use crate::SAMPLE_RATE;
+use param_derive::Params;
+
+const MIN_CUTOFF: f64 = 20.0;
+const MAX_CUTOFF: f64 = 20_000.0;
+const MODES: [&str; 4] = ["Low-pass", "High-pass", "Band-pass", "Notch"];
+// Drive amount (1dB) over which the saturation is faded in
+const SATURATION_FADE: f32 = 1.122;
+
+#[derive(Params)]
+pub struct FilterParams {
+    mode: Param,
+    cutoff: Param,
+    resonance: Param,
+    drive: Param,
+}
+
+impl Default for FilterParams {
+    fn default() -> Self {
+        Self {
+            mode: Param::new(
+                0.0,
+                ParamInfo::new("Mode", 0, MODES.len() as i32 - 1)
+                    .with_steps([1, 1])
+                    .with_formatter(|v| MODES[v as usize].to_string()),
+            ),
+            cutoff: Param::new(
+                1.0,
+                ParamInfo::new("Cutoff", 0.0, 1.0)
+                    .with_steps([0.005, 0.05])
+                    .with_smoothing(params::ExpSmoothing::default())
+                    .with_map(cutoff_to_hz)
+                    .with_formatter(|v| format!("{:.0}Hz", cutoff_to_hz(v))),
+            ),
+            resonance: Param::new(
+                0.0,
+                ParamInfo::new("Resonance", 0.0, 1.0)
+                    .with_smoothing(params::ExpSmoothing::default()),
+            ),
+            drive: Param::new(
+                0.0,
+                ParamInfo::new("Drive", 0, 24)
+                    .with_steps([0.25, 1.0])
+                    .with_smoothing(params::ExpSmoothing::default())
+                    .with_map(params::db_to_amp),
+            ),
+        }
+    }
+}
+
+/// Map the normalized cutoff parameter to a frequency, so that sweeps sound even across the
+/// whole frequency range.
+fn cutoff_to_hz(v: f64) -> f64 {
+    MIN_CUTOFF * (MAX_CUTOFF / MIN_CUTOFF).powf(v)
+}
+
+/// Stereo state variable filter, based on Andrew Simper's trapezoidal integrated SVF. Drive
+/// amplifies the input into a tanh saturator before it's filtered. Without drive the saturator is
+/// bypassed.
+pub struct Filter {
+    params: Arc<FilterParams>,
+    ic1eq: [f32; 2],
+    ic2eq: [f32; 2],
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self {
+            params: Arc::new(FilterParams::default()),
+            ic1eq: [0.0; 2],
+            ic2eq: [0.0; 2],
+        }
+    }
+}
+
+impl Effect for Filter {
+    fn process(&mut self, _ctx: &EffectContext, buf: &mut [Stereo]) -> ProcessStatus {
+        let mode = self.params.mode.value() as usize;
+        for frame in buf.iter_mut() {
+            let cutoff = self.params.cutoff.value();
+            let k = 2.0 - 1.95 * self.params.resonance.value();
+            let drive = self.params.drive.value() as f32;
+            // Fading in instead of switching avoids a jump in level when drive is turned down
+            let saturation = f32::min(1.0, (drive - 1.0) / (SATURATION_FADE - 1.0));
+
+            let g = (PI * cutoff / SAMPLE_RATE).tan();
+            let a1 = 1.0 / (1.0 + g * (g + k));
+            let a2 = g * a1;
+            let a3 = g * a2;
+            let (a1, a2, a3, k) = (a1 as f32, a2 as f32, a3 as f32, k as f32);
+
+            let mut out = [0.0; 2];
+            for (ch, out) in out.iter_mut().enumerate() {
+                let x = frame.channel(ch);
+                let v0 = x + saturation * ((x * drive).tanh() - x);
+                let v3 = v0 - self.ic2eq[ch];
+                let v1 = a1 * self.ic1eq[ch] + a2 * v3;
+                let v2 = self.ic2eq[ch] + a2 * self.ic1eq[ch] + a3 * v3;
+                self.ic1eq[ch] = 2.0 * v1 - self.ic1eq[ch];
+                self.ic2eq[ch] = 2.0 * v2 - self.ic2eq[ch];
+
+                *out = match mode {
+                    0 => v2,
+                    1 => v0 - k * v1 - v2,
+                    2 => k * v1,
+                    _ => v0 - k * v1,
+                };
+            }
+            *frame = Stereo::new(out);
+        }
+
+        let silent = |state: &[f32; 2]| state.iter().all(|v| v.abs() < 1e-6);
+        if silent(&self.ic1eq) && silent(&self.ic2eq) {
+            ProcessStatus::Idle
+        } else {
+            ProcessStatus::Continue
+        }
+    }
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hz_to_cutoff(hz: f64) -> f64 {
+        (hz / MIN_CUTOFF).ln() / (MAX_CUTOFF / MIN_CUTOFF).ln()
+    }
+
+    // Filter a sine wave and return the output level relative to the input in dB
+    fn attenuation(filter: &mut Filter, freq: f64) -> f64 {
+        attenuation_at(filter, freq, 0.1)
+    }
+
+    fn attenuation_at(filter: &mut Filter, freq: f64, amp: f64) -> f64 {
+        let mut buf: Vec<Stereo> = (0..SAMPLE_RATE as usize / 2)
+            .map(|i| {
+                let v = amp * (2.0 * PI * freq * i as f64 / SAMPLE_RATE).sin();
+                Stereo::new([v as f32, v as f32])
+            })
+            .collect();
+        let ctx = EffectContext::new(120, 4);
+        filter.process(&ctx, &mut buf);
+
+        // Skip the first half to let the filter settle
+        let settled = &buf[buf.len() / 2..];
+        let peak = settled
+            .iter()
+            .map(|frame| frame.channel(0).abs())
+            .fold(0.0, f32::max);
+        20.0 * (peak as f64 / amp).log10()
+    }
+
+    fn filter(mode: usize, cutoff: f64) -> Filter {
+        let filter = Filter::new();
+        filter.params.mode.set(mode as f64);
+        filter.params.cutoff.set(hz_to_cutoff(cutoff));
+        filter
+    }
+
+    #[test]
+    fn low_pass() {
+        assert!(attenuation(&mut filter(0, 1000.0), 100.0) > -0.5);
+        assert!(attenuation(&mut filter(0, 1000.0), 1000.0) < -2.5);
+        // 12dB per octave, three octaves above the cutoff
+        assert!(attenuation(&mut filter(0, 1000.0), 8000.0) < -30.0);
+    }
+
+    #[test]
+    fn high_pass() {
+        assert!(attenuation(&mut filter(1, 1000.0), 100.0) < -30.0);
+        assert!(attenuation(&mut filter(1, 1000.0), 8000.0) > -0.5);
+    }
+
+    #[test]
+    fn band_pass_and_notch() {
+        assert!(attenuation(&mut filter(2, 1000.0), 1000.0) > -0.5);
+        assert!(attenuation(&mut filter(2, 1000.0), 8000.0) < -12.0);
+        assert!(attenuation(&mut filter(3, 1000.0), 1000.0) < -30.0);
+        assert!(attenuation(&mut filter(3, 1000.0), 8000.0) > -1.0);
+    }
+
+    #[test]
+    fn drive() {
+        // Loud signals are left alone without drive, and saturated instead of boosted with it
+        assert!(attenuation_at(&mut filter(0, 20_000.0), 100.0, 0.9) > -0.1);
+        let mut driven = filter(0, 20_000.0);
+        driven.params.drive.set(6.0);
+        assert!(attenuation_at(&mut driven, 100.0, 0.9) < 1.0);
+    }
+}
@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use crate::audio::Stereo;
+use crate::engine::{Effect, EffectContext, ProcessStatus};
+use crate::params::{self, Param, ParamInfo, Params};
+use param_derive::Params;
+
+#[derive(Params)]
+pub struct GainParams {
+    gain: Param,
+    invert: Param,
+}
+
+impl Default for GainParams {
+    fn default() -> Self {
+        Self {
+            gain: Param::new(
+                0.0,
+                ParamInfo::new("Gain", -60, 24)
+                    .with_steps([0.25, 1.0])
+                    .with_smoothing(params::ExpSmoothing::default())
+                    .with_map(params::db_to_amp),
+            ),
+            invert: Param::new(0.0, ParamInfo::bool("Invert Phase", 1.0)),
+        }
+    }
+}
+
+/// Utility effect for changing the level of a track
+pub struct Gain {
+    params: Arc<GainParams>,
+}
+
+impl Gain {
+    pub fn new() -> Self {
+        Self {
+            params: Arc::new(GainParams::default()),
+        }
+    }
+}
+
+impl Effect for Gain {
+    fn process(&mut self, _ctx: &EffectContext, buf: &mut [Stereo]) -> ProcessStatus {
+        let sign = if self.params.invert.as_bool() {
+            -1.0
+        } else {
+            1.0
+        };
+        for frame in buf.iter_mut() {
+            *frame = *frame * self.params.gain.value() as f32 * sign;
+        }
+        ProcessStatus::Idle
+    }
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use atomic_float::AtomicF64;
+
+use crate::audio::Stereo;
+use crate::effects::compressor::time_constant;
+use crate::engine::{Effect, EffectContext, ProcessStatus};
+use crate::params::{self, format_millis, Param, ParamInfo, Params};
+use crate::SAMPLE_RATE;
+use param_derive::Params;
+
+const LOOKAHEAD_MS: usize = 5;
+
+#[derive(Params)]
+pub struct LimiterParams {
+    ceiling: Param,
+    release: Param,
+}
+
+impl Default for LimiterParams {
+    fn default() -> Self {
+        Self {
+            ceiling: Param::new(
+                -0.3,
+                ParamInfo::new("Ceiling", -24, 0)
+                    .with_steps([0.1, 1.0])
+                    .with_map(params::db_to_amp),
+            ),
+            release: Param::new(
+                100.0,
+                ParamInfo::new("Release", 1, 1000)
+                    .with_steps([1, 10])
+                    .with_formatter(format_millis),
+            ),
+        }
+    }
+}
+
+/// Brickwall limiter that looks ahead a few milliseconds, so the gain can be reduced gradually
+/// before a peak arrives. This delays the signal by the lookahead time.
+pub struct Limiter {
+    params: Arc<LimiterParams>,
+    lookahead: usize,
+    /// Input delayed by the lookahead time
+    delay: Vec<Stereo>,
+    /// Gain over the last `lookahead` frames, used for smoothing gain changes
+    gains: Vec<f32>,
+    gain_sum: f64,
+    pos: usize,
+    /// Minimum of the required gain over the lookahead window. Holds pairs of frame index and
+    /// gain, with increasing gains.
+    min_gain: VecDeque<(usize, f32)>,
+    frame: usize,
+    release_gain: f32,
+    gain_reduction: Arc<AtomicF64>,
+}
+
+impl Limiter {
+    pub fn new() -> Self {
+        let lookahead = LOOKAHEAD_MS * SAMPLE_RATE as usize / 1000;
+        Self {
+            params: Arc::new(LimiterParams::default()),
+            lookahead,
+            delay: vec![Stereo::ZERO; lookahead],
+            gains: vec![1.0; lookahead],
+            gain_sum: lookahead as f64,
+            pos: 0,
+            min_gain: VecDeque::with_capacity(lookahead + 2),
+            frame: 0,
+            release_gain: 1.0,
+            gain_reduction: Arc::new(AtomicF64::new(0.0)),
+        }
+    }
+
+    /// Returns the lowest required gain over the current frame and the `lookahead` frames
+    /// before it.
+    fn hold(&mut self, gain: f32) -> f32 {
+        while matches!(self.min_gain.back(), Some((_, g)) if *g >= gain) {
+            self.min_gain.pop_back();
+        }
+        self.min_gain.push_back((self.frame, gain));
+        while matches!(self.min_gain.front(), Some((i, _)) if i + self.lookahead < self.frame) {
+            self.min_gain.pop_front();
+        }
+        self.frame += 1;
+        self.min_gain.front().unwrap().1
+    }
+}
+
+impl Effect for Limiter {
+    fn process(&mut self, _ctx: &EffectContext, buf: &mut [Stereo]) -> ProcessStatus {
+        let ceiling = self.params.ceiling.value() as f32;
+        let release = time_constant(self.params.release.value());
+        let mut min_gain: f32 = 1.0;
+
+        for frame in buf.iter_mut() {
+            let peak = frame.channel(0).abs().max(frame.channel(1).abs());
+            let required = if peak > ceiling { ceiling / peak } else { 1.0 };
+            let held = self.hold(required);
+
+            // Reduce gain immediately, but recover slowly
+            if held < self.release_gain {
+                self.release_gain = held;
+            } else {
+                self.release_gain = release * self.release_gain + (1.0 - release) * held;
+            }
+
+            // Averaging the gain over the lookahead window ramps it down before the peak comes
+            // out of the delay line. Every gain in the window is at most the gain required for
+            // that peak, so the average is too.
+            self.gain_sum += (self.release_gain - self.gains[self.pos]) as f64;
+            self.gains[self.pos] = self.release_gain;
+            let gain = (self.gain_sum / self.lookahead as f64) as f32;
+            min_gain = min_gain.min(gain);
+
+            let delayed = self.delay[self.pos];
+            self.delay[self.pos] = *frame;
+            self.pos = (self.pos + 1) % self.lookahead;
+
+            // Guard against accumulated rounding errors in the running sum
+            *frame = (delayed * gain).map(|v| v.clamp(-ceiling, ceiling));
+        }
+        self.gain_reduction
+            .store(-20.0 * min_gain.log10() as f64, Ordering::Relaxed);
+
+        ProcessStatus::Idle
+    }
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+
+    fn gain_reduction(&self) -> Option<Arc<AtomicF64>> {
+        Some(self.gain_reduction.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_stays_below_ceiling() {
+        let mut limiter = Limiter::new();
+        let ctx = EffectContext::new(120, 4);
+        let ceiling = limiter.params.ceiling.value() as f32;
+        limiter.params.release.set(1.0);
+
+        let mut buf: Vec<Stereo> = (0..SAMPLE_RATE as usize)
+            .map(|i| {
+                let v = (i as f32 * 0.05).sin() * if i % 1000 < 10 { 4.0 } else { 0.5 };
+                Stereo::new([v, -v])
+            })
+            .collect();
+        let input = buf.clone();
+        for chunk in buf.chunks_mut(128) {
+            limiter.process(&ctx, chunk);
+        }
+
+        let lookahead = limiter.lookahead;
+        for (i, frame) in buf.iter().enumerate() {
+            assert!(frame.channel(0).abs() <= ceiling);
+            // Quiet parts away from the peaks pass through unchanged
+            if i >= lookahead && (500..750).contains(&((i - lookahead) % 1000)) {
+                let expected = input[i - lookahead].channel(0);
+                assert!((frame.channel(0) - expected).abs() < 1e-2);
+            }
+        }
+        assert!(limiter.gain_reduction.load(Ordering::Relaxed) >= 0.0);
+    }
+}
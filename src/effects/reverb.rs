@@ -0,0 +1,224 @@
+use std::sync::Arc;
+
+use crate::audio::Stereo;
+use crate::engine::{Effect, EffectContext, ProcessStatus};
+use crate::params::{self, format_millis, Param, ParamInfo, Params};
+use crate::SAMPLE_RATE;
+use param_derive::Params;
+
+// Delay line lengths from the original Freeverb, tuned for 44.1kHz
+const COMB_TUNING: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNING: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+const MAX_PRE_DELAY_MS: usize = 250;
+const INPUT_GAIN: f32 = 0.015;
+const SILENCE: f32 = 1e-5;
+
+#[derive(Params)]
+pub struct ReverbParams {
+    size: Param,
+    damping: Param,
+    pre_delay: Param,
+    width: Param,
+    mix: Param,
+}
+
+impl Default for ReverbParams {
+    fn default() -> Self {
+        Self {
+            size: Param::new(
+                0.5,
+                ParamInfo::new("Size", 0.0, 1.0).with_smoothing(params::ExpSmoothing::default()),
+            ),
+            damping: Param::new(
+                0.5,
+                ParamInfo::new("Damping", 0.0, 1.0).with_smoothing(params::ExpSmoothing::default()),
+            ),
+            pre_delay: Param::new(
+                0.0,
+                ParamInfo::new("Pre-delay", 0, MAX_PRE_DELAY_MS as i32)
+                    .with_steps([1, 10])
+                    .with_formatter(format_millis),
+            ),
+            width: Param::new(
+                1.0,
+                ParamInfo::new("Width", 0.0, 1.0).with_smoothing(params::ExpSmoothing::default()),
+            ),
+            mix: Param::new(
+                0.3,
+                ParamInfo::new("Mix", 0.0, 1.0).with_smoothing(params::ExpSmoothing::default()),
+            ),
+        }
+    }
+}
+
+fn scale(len: usize) -> usize {
+    (len as f64 * SAMPLE_RATE / 44100.0) as usize
+}
+
+struct Comb {
+    buf: Vec<f32>,
+    pos: usize,
+    filter_store: f32,
+}
+
+impl Comb {
+    fn new(len: usize) -> Self {
+        Self {
+            buf: vec![0.0; len],
+            pos: 0,
+            filter_store: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buf[self.pos];
+        self.filter_store = output * (1.0 - damping) + self.filter_store * damping;
+        self.buf[self.pos] = input + self.filter_store * feedback;
+        self.pos = (self.pos + 1) % self.buf.len();
+        output
+    }
+}
+
+struct Allpass {
+    buf: Vec<f32>,
+    pos: usize,
+}
+
+impl Allpass {
+    fn new(len: usize) -> Self {
+        Self {
+            buf: vec![0.0; len],
+            pos: 0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let buffered = self.buf[self.pos];
+        self.buf[self.pos] = input + buffered * 0.5;
+        self.pos = (self.pos + 1) % self.buf.len();
+        buffered - input
+    }
+}
+
+/// Freeverb style reverb: a set of parallel comb filters followed by allpass filters in series,
+/// per channel. The right channel uses slightly longer delay lines to decorrelate the channels.
+pub struct Reverb {
+    params: Arc<ReverbParams>,
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+    pre_delay: Vec<Stereo>,
+    pre_delay_pos: usize,
+    /// Number of consecutive frames in which neither the input nor any of the comb filters
+    /// produced sound
+    silent_frames: usize,
+    tail_frames: usize,
+}
+
+impl Reverb {
+    pub fn new() -> Self {
+        let combs = |spread| {
+            COMB_TUNING
+                .iter()
+                .map(|len| Comb::new(scale(len + spread)))
+                .collect()
+        };
+        let allpasses = |spread| {
+            ALLPASS_TUNING
+                .iter()
+                .map(|len| Allpass::new(scale(len + spread)))
+                .collect()
+        };
+        let pre_delay_len = MAX_PRE_DELAY_MS * SAMPLE_RATE as usize / 1000 + 1;
+        let max_comb = scale(COMB_TUNING.iter().max().unwrap() + STEREO_SPREAD);
+        Self {
+            params: Arc::new(ReverbParams::default()),
+            combs: [combs(0), combs(STEREO_SPREAD)],
+            allpasses: [allpasses(0), allpasses(STEREO_SPREAD)],
+            pre_delay: vec![Stereo::ZERO; pre_delay_len],
+            pre_delay_pos: 0,
+            silent_frames: 0,
+            tail_frames: pre_delay_len + max_comb,
+        }
+    }
+}
+
+impl Effect for Reverb {
+    fn process(&mut self, _ctx: &EffectContext, buf: &mut [Stereo]) -> ProcessStatus {
+        let pre_delay = self.params.pre_delay.value() as usize * SAMPLE_RATE as usize / 1000;
+        let len = self.pre_delay.len();
+
+        for frame in buf.iter_mut() {
+            let feedback = 0.7 + 0.28 * self.params.size.value() as f32;
+            let damping = 0.4 * self.params.damping.value() as f32;
+            let width = self.params.width.value() as f32;
+            let mix = self.params.mix.value() as f32;
+
+            self.pre_delay[self.pre_delay_pos] = *frame;
+            let delayed = self.pre_delay[(self.pre_delay_pos + len - pre_delay) % len];
+            self.pre_delay_pos = (self.pre_delay_pos + 1) % len;
+
+            let input = (delayed.channel(0) + delayed.channel(1)) * INPUT_GAIN;
+            let mut peak = frame.channel(0).abs().max(frame.channel(1).abs());
+            let mut out = [0.0; 2];
+            for (ch, out) in out.iter_mut().enumerate() {
+                for comb in &mut self.combs[ch] {
+                    let v = comb.process(input, feedback, damping);
+                    peak = peak.max(v.abs());
+                    *out += v;
+                }
+                for allpass in &mut self.allpasses[ch] {
+                    *out = allpass.process(*out);
+                }
+            }
+
+            if peak < SILENCE {
+                self.silent_frames += 1;
+            } else {
+                self.silent_frames = 0;
+            }
+
+            let wet1 = width / 2.0 + 0.5;
+            let wet2 = (1.0 - width) / 2.0;
+            let wet = Stereo::new([out[0] * wet1 + out[1] * wet2, out[1] * wet1 + out[0] * wet2]);
+            *frame = *frame * (1.0 - mix) + wet * mix;
+        }
+
+        // The tail has decayed once everything in the delay lines has been silent
+        if self.silent_frames > self.tail_frames {
+            ProcessStatus::Idle
+        } else {
+            ProcessStatus::Continue
+        }
+    }
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_after_tail() {
+        let mut reverb = Reverb::new();
+        let ctx = EffectContext::new(120, 4);
+
+        let mut buf = vec![Stereo::ZERO; 128];
+        buf[0] = Stereo::new([1.0, 1.0]);
+        let mut frames = 0;
+        let mut tail = false;
+        while let ProcessStatus::Continue = reverb.process(&ctx, &mut buf) {
+            frames += buf.len();
+            tail |= frames > SAMPLE_RATE as usize && buf.iter().any(|f| f.channel(0) != 0.0);
+            buf.fill(Stereo::ZERO);
+            assert!(
+                frames < 60 * SAMPLE_RATE as usize,
+                "reverb never became idle"
+            );
+        }
+        assert!(tail, "expected a tail of more than a second");
+    }
+}
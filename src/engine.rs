@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 use std::ops::Range;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::vec;
 
@@ -8,38 +8,79 @@ use atomic_float::AtomicF64;
 use ringbuf::Consumer;
 use triple_buffer::Input;
 
-use crate::app::{AppState, DeviceId, EngineState, TrackId};
-use crate::audio::{Buffer, Rms, Stereo};
-use crate::params::{self, Param, ParamInfo, Params};
-use crate::pattern::{Note, DEFAULT_VELOCITY};
+use crate::app::{AppState, DeviceId, EngineState, PatternId, TrackId};
+use crate::audio::{Buffer, Peak, Rms, Stereo};
+use crate::loudness::LoudnessMeter;
+use crate::mixer::Mixer;
+use crate::params::{self, Param, ParamInfo, Params, Smoothing};
+use crate::pattern::{Note, Step, DEFAULT_VELOCITY, MAX_NOTE_COLUMNS, MAX_PATTERNS};
 use crate::{INTERNAL_BUFFER_SIZE, SAMPLE_RATE};
 use param_derive::Params;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
+/// Number of instrument tracks in a new project
 pub const INSTRUMENT_TRACKS: usize = 16;
+pub const MAX_INSTRUMENT_TRACKS: usize = 64;
 pub const PREVIEW_INSTRUMENTS_CACHE_SIZE: usize = 10;
-pub const MAX_INSTRUMENTS: usize = INSTRUMENT_TRACKS + PREVIEW_INSTRUMENTS_CACHE_SIZE;
-pub const TOTAL_TRACKS: usize = INSTRUMENT_TRACKS + 1; // add 1 for master track
+/// Size limit of the instrument list, pattern steps store the instrument index in a `u8`
+pub const MAX_INSTRUMENTS: usize = u8::MAX as usize;
+pub const MAX_BUSES: usize = 8;
+pub const TOTAL_TRACKS: usize = MAX_INSTRUMENT_TRACKS + MAX_BUSES + 1; // add 1 for master track
 pub const TICKS_PER_LINE: usize = 12;
+pub const MAX_EFFECTS: usize = 16;
+pub const NUM_SENDS: usize = 2;
 
 const RMS_WINDOW_SIZE: usize = SAMPLE_RATE as usize / 10 * 3;
+const PEAK_HOLD_SECONDS: f64 = 1.5;
+const PEAK_DECAY_DB_PER_SECOND: f64 = 20.0;
 
 pub enum EngineCommand {
-    CreateTrack(TrackId, Box<Track>),
+    CreateTrack(TrackId, basedrop::Owned<Track>),
+    DeleteTrack(TrackId),
     CreateInstrument(DeviceId, basedrop::Owned<Box<dyn Plugin + Send>>),
     DeleteInstrument(DeviceId),
     PlayNote(DeviceId, TrackId, u8),
+    AddEffect(
+        TrackId,
+        usize,
+        DeviceId,
+        basedrop::Owned<Box<dyn Effect + Send>>,
+    ),
+    RemoveEffect(TrackId, DeviceId),
+    SetSidechain(TrackId, DeviceId, Option<TrackId>),
+    MoveEffect(TrackId, DeviceId, usize),
+    BypassEffect(TrackId, DeviceId, bool),
 }
 
 pub struct Engine {
     state: EngineState,
     state_buf: Input<EngineState>,
     instruments: HashMap<DeviceId, Device>,
-    tracks: HashMap<TrackId, Box<Track>>,
+    tracks: HashMap<TrackId, basedrop::Owned<Track>>,
     master: Track,
+    master_id: TrackId,
     preview_track_id: TrackId,
     consumer: Consumer<EngineCommand>,
+    /// Output of the track that's being processed, before it's added to its bus
+    scratch: Buffer,
+    /// Send outputs of the track that's being processed
+    sends: [Buffer; NUM_SENDS],
+    mixer: Mixer,
+    loudness: LoudnessMeter,
+    was_playing: bool,
     samples_to_tick: usize,
     total_ticks: u64,
+    /// When set, playback stops after this pattern has finished playing instead of continuing
+    /// with the next one. Used for offline rendering.
+    end_pattern: Option<usize>,
+    end_reached: bool,
+    finished: bool,
+    /// Number of times each pattern has been played since playback started, for conditional
+    /// trigs
+    pattern_passes: HashMap<PatternId, u32>,
+    /// Random numbers for trigger probabilities, can be seeded for reproducible renders
+    rng: StdRng,
 }
 
 impl Engine {
@@ -48,14 +89,18 @@ impl Engine {
         state_buf: Input<EngineState>,
         consumer: Consumer<EngineCommand>,
         master: Track,
+        master_id: TrackId,
         preview_track_id: TrackId,
+        preview_track: basedrop::Owned<Track>,
     ) -> Engine {
-        let mut tracks = HashMap::with_capacity(TOTAL_TRACKS);
-        tracks.insert(preview_track_id, Box::new(Track::new()));
+        // Add 1 for the preview track
+        let mut tracks = HashMap::with_capacity(TOTAL_TRACKS + 1);
+        tracks.insert(preview_track_id, preview_track);
 
         // Double the capacity here. Deleting instruments is asynchronous
         // so we might have a few more in flight than the max
-        let instruments = HashMap::with_capacity(2 * MAX_INSTRUMENTS);
+        let instruments =
+            HashMap::with_capacity(2 * (MAX_INSTRUMENTS + PREVIEW_INSTRUMENTS_CACHE_SIZE));
 
         Self {
             instruments,
@@ -63,13 +108,63 @@ impl Engine {
             state,
             state_buf,
             master,
+            master_id,
             preview_track_id,
             consumer,
+            scratch: vec![Stereo::ZERO; INTERNAL_BUFFER_SIZE],
+            sends: std::array::from_fn(|_| vec![Stereo::ZERO; INTERNAL_BUFFER_SIZE]),
+            mixer: Mixer::new(),
+            loudness: LoudnessMeter::new(SAMPLE_RATE),
+            was_playing: false,
             samples_to_tick: 0,
             total_ticks: 0,
+            end_pattern: None,
+            end_reached: false,
+            finished: false,
+            // Entries of deleted patterns are removed before this fills up
+            pattern_passes: HashMap::with_capacity(2 * MAX_PATTERNS),
+            rng: StdRng::from_entropy(),
         }
     }
 
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Play the song from pattern `start` until pattern `end` has finished, then release all
+    /// playing notes.
+    pub fn play_range(&mut self, start: usize, end: usize) {
+        self.state.current_pattern = start;
+        self.state.current_tick = 0;
+        self.samples_to_tick = 0;
+        self.end_pattern = Some(end);
+        self.end_reached = false;
+        self.finished = false;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Returns the post fader output of a track for the last processed buffer
+    pub fn track_output(&self, track_id: TrackId, num_frames: usize) -> &[Stereo] {
+        &self.tracks.get(&track_id).unwrap().out[..num_frames]
+    }
+
+    /// Returns true when none of the instruments and effects are producing sound anymore.
+    pub fn is_idle(&self) -> bool {
+        self.instruments.values().all(|instr| instr.is_idle())
+            && self.tracks.values().all(|track| track.is_idle())
+            && self.master.is_idle()
+    }
+
+    fn track_mut(&mut self, track_id: TrackId) -> &mut Track {
+        if track_id == self.master_id {
+            return &mut self.master;
+        }
+        self.tracks.get_mut(&track_id).unwrap()
+    }
+
     fn tick(&mut self, state: &AppState, num_frames: usize) {
         let mut num_frames = num_frames;
         let mut offset = 0;
@@ -88,7 +183,13 @@ impl Engine {
     }
 
     fn dispatch_events(&mut self, state: &AppState, offset: usize) {
-        if !state.is_playing {
+        if !state.is_playing || self.finished {
+            return;
+        }
+        if self.end_reached {
+            // Release notes on the first tick after the end of the last pattern
+            self.release_notes(offset);
+            self.finished = true;
             return;
         }
         let mut curr_pattern = self.state.current_pattern;
@@ -98,39 +199,123 @@ impl Engine {
             curr_pattern = state.next_pattern(curr_pattern);
             state.pattern(curr_pattern).unwrap()
         });
+        if self.state.current_tick >= pattern.ticks() {
+            // The pattern can get shorter while we're playing it, e.g. when loading a project
+            self.state.current_tick = 0;
+        }
 
-        for event in pattern.events(self.state.current_tick) {
-            if let Some(instr) = &state.instruments[event.instrument] {
-                let track_id = state.tracks[event.track].id;
-                let track = self.tracks.get_mut(&track_id).unwrap();
+        let pattern_id = state.song[curr_pattern];
+        if self.state.current_tick.is_multiple_of(TICKS_PER_LINE) {
+            let line = self.state.current_tick / TICKS_PER_LINE;
+            let pass = self.pattern_passes.get(&pattern_id).copied().unwrap_or(0);
+            for (i, track) in state.tracks.iter().enumerate().take(pattern.tracks.len()) {
+                let step = &pattern.steps(i)[line];
+                let skip = !self.is_step_played(step, pass, state.fill);
+                if let Some(track) = self.tracks.get_mut(&track.id) {
+                    track.skip_line = skip;
+                }
+            }
+        }
 
-                if let Some((tick, instr_id)) = track.last_event {
-                    if tick != self.total_ticks {
+        for event in pattern.events(self.state.current_tick) {
+            let track_id = state.tracks[event.track].id;
+            // The state can still refer to a track that was just deleted, as commands are
+            // processed before the state that goes with them is published
+            let Some(track) = self.tracks.get_mut(&track_id) else {
+                continue;
+            };
+            if track.skip_line {
+                continue;
+            }
+            let column = event.column;
+            let last_event = &mut track.last_events[column];
+
+            match event.note {
+                Note::Off => {
+                    // Releases the note playing in the column, whichever instrument it was
+                    // started on
+                    if let Some((_, instr_id)) = last_event.take() {
                         let instr = self.instruments.get_mut(&instr_id).unwrap();
-                        instr.send_event(Event::new(offset, track_id, Note::Off));
+                        instr.send_event(Event::new(offset, track_id, column, Note::Off));
                     }
+                    continue;
                 }
+                Note::Slide(..) | Note::Glide(..) => {
+                    // Pitch bends apply to the note playing in the column
+                    if let Some((_, instr_id)) = *last_event {
+                        let instr = self.instruments.get_mut(&instr_id).unwrap();
+                        instr.send_event(Event::new(offset, track_id, column, event.note));
+                    }
+                    continue;
+                }
+                Note::On(..) => {}
+            }
 
-                track.last_event = Some((self.total_ticks, instr.id));
-                if let Note::Off = event.note {
-                    track.last_event = None;
+            if let Some(instr) = state
+                .instruments
+                .get(event.instrument)
+                .and_then(Option::as_ref)
+            {
+                if let Some((tick, instr_id)) = *last_event {
+                    if tick != self.total_ticks {
+                        let instr = self.instruments.get_mut(&instr_id).unwrap();
+                        instr.send_event(Event::new(offset, track_id, column, Note::Off));
+                    }
                 }
 
+                *last_event = Some((self.total_ticks, instr.id));
                 let instr = self.instruments.get_mut(&instr.id).unwrap();
-                instr.send_event(Event::new(offset, track_id, event.note));
+                instr.send_event(Event::new(offset, track_id, column, event.note));
             }
         }
 
         self.state.current_tick += 1;
         if self.state.current_tick >= pattern.ticks() {
             self.state.current_tick = 0;
-            curr_pattern = state.next_pattern(curr_pattern);
+            if self.pattern_passes.len() >= MAX_PATTERNS {
+                self.pattern_passes
+                    .retain(|id, _| state.patterns.contains_key(id));
+            }
+            *self.pattern_passes.entry(pattern_id).or_insert(0) += 1;
+            if self.end_pattern == Some(curr_pattern) {
+                self.end_reached = true;
+            } else {
+                curr_pattern = state.next_pattern(curr_pattern);
+            }
         }
         self.state.current_pattern = curr_pattern;
     }
 
+    /// Evaluates the trigger condition and probability of a step
+    fn is_step_played(&mut self, step: &Step, pass: u32, fill: bool) -> bool {
+        if let Some(condition) = step.condition() {
+            if !condition.is_met(pass, fill) {
+                return false;
+            }
+        }
+        match step.probability() {
+            Some(probability) => self.rng.gen_range(0..100) < probability,
+            None => true,
+        }
+    }
+
+    fn release_notes(&mut self, offset: usize) {
+        for (track_id, track) in &mut self.tracks {
+            for (column, last_event) in track.last_events.iter_mut().enumerate() {
+                if let Some((_, instr_id)) = last_event.take() {
+                    if let Some(instr) = self.instruments.get_mut(&instr_id) {
+                        instr.send_event(Event::new(offset, *track_id, column, Note::Off));
+                    }
+                }
+            }
+        }
+    }
+
     pub fn process(&mut self, state: &AppState, buffer: &mut [Stereo]) {
         self.run_commands(state);
+        if state.is_playing && !self.was_playing {
+            self.pattern_passes.clear();
+        }
         self.tick(state, buffer.len());
 
         for instr in &mut self.instruments.values_mut() {
@@ -138,17 +323,47 @@ impl Engine {
             instr.process(&mut ctx);
         }
 
-        for (id, track) in self.tracks.iter_mut() {
-            if *id == self.preview_track_id {
-                // Preview track processes directly into the output buffer
+        let num_frames = buffer.len();
+        let ctx = EffectContext::new(state.bpm, state.lines_per_beat);
+        self.mixer.update(&state.track_order);
+        for (idx, route) in state.track_order.iter().enumerate() {
+            // Take the track out of the map while it's processed, so its effects can read the
+            // output of the tracks that were processed before it.
+            let Some(mut track) = self.tracks.remove(&route.id) else {
                 continue;
+            };
+            let out = &mut self.scratch[..num_frames];
+            out.fill(Stereo::ZERO);
+            let silenced = self.mixer.is_silenced(idx);
+            track.process(&ctx, &self.tracks, out, &mut self.sends, silenced);
+            self.tracks.insert(route.id, track);
+
+            let bus = match route.output.and_then(|id| self.tracks.get_mut(&id)) {
+                Some(bus) => &mut bus.buf[..num_frames],
+                None => &mut self.master.buf[..num_frames],
+            };
+            mix(bus, out);
+
+            for (send, bus_id) in self.sends.iter().zip(route.sends) {
+                if let Some(bus) = bus_id.and_then(|id| self.tracks.get_mut(&id)) {
+                    mix(&mut bus.buf[..num_frames], &send[..num_frames]);
+                }
             }
-            track.process(&mut self.master.buf[..buffer.len()]);
         }
-        self.master.process(buffer);
+        self.master
+            .process(&ctx, &self.tracks, buffer, &mut self.sends, false);
+
+        if state.is_playing && !self.was_playing {
+            self.loudness.reset();
+        }
+        self.was_playing = state.is_playing;
+        self.loudness.process(&self.master.out[..num_frames]);
+        self.state.loudness = self.loudness.value();
 
-        let preview = self.tracks.get_mut(&self.preview_track_id).unwrap();
-        preview.process(buffer);
+        // Preview track processes directly into the output buffer
+        let mut preview = self.tracks.remove(&self.preview_track_id).unwrap();
+        preview.process(&ctx, &self.tracks, buffer, &mut self.sends, false);
+        self.tracks.insert(self.preview_track_id, preview);
 
         self.state_buf.input_buffer().clone_from(&self.state);
         self.state_buf.publish();
@@ -160,6 +375,17 @@ impl Engine {
                 EngineCommand::CreateTrack(track_id, track) => {
                     self.tracks.insert(track_id, track);
                 }
+                EngineCommand::DeleteTrack(track_id) => {
+                    if let Some(mut track) = self.tracks.remove(&track_id) {
+                        for (column, last_event) in track.last_events.iter_mut().enumerate() {
+                            if let Some((_, instr_id)) = last_event.take() {
+                                if let Some(instr) = self.instruments.get_mut(&instr_id) {
+                                    instr.send_event(Event::new(0, track_id, column, Note::Off));
+                                }
+                            }
+                        }
+                    }
+                }
                 EngineCommand::CreateInstrument(device_id, instrument) => {
                     self.instruments.insert(device_id, Device::new(instrument));
                 }
@@ -167,27 +393,60 @@ impl Engine {
                     self.instruments.retain(|_, d| !d.deleted || !d.is_idle());
                     let instr = self.instruments.get_mut(&device_id).unwrap();
                     for (track_id, track) in &mut self.tracks {
-                        if let Some((_, id)) = track.last_event {
-                            if id == device_id {
-                                track.last_event = None;
-                                instr.send_event(Event::new(0, *track_id, Note::Off))
+                        for (column, last_event) in track.last_events.iter_mut().enumerate() {
+                            if let Some((_, id)) = *last_event {
+                                if id == device_id {
+                                    *last_event = None;
+                                    instr.send_event(Event::new(0, *track_id, column, Note::Off))
+                                }
                             }
                         }
                     }
                     instr.delete();
                 }
                 EngineCommand::PlayNote(device_id, track_id, pitch) => {
-                    let track = self.tracks.get_mut(&track_id).unwrap();
-                    if let Some((_, instr_id)) = track.last_event {
+                    let Some(track) = self.tracks.get_mut(&track_id) else {
+                        continue;
+                    };
+                    if let Some((_, instr_id)) = track.last_events[0] {
                         if track_id == self.preview_track_id {
                             let instr = self.instruments.get_mut(&instr_id).unwrap();
-                            instr.send_event(Event::new(0, track_id, Note::Off));
+                            instr.send_event(Event::new(0, track_id, 0, Note::Off));
                         }
                     }
                     let instr = self.instruments.get_mut(&device_id).unwrap();
                     let note = Note::On(pitch, DEFAULT_VELOCITY);
-                    track.last_event = Some((0, device_id));
-                    instr.send_event(Event::new(0, track_id, note));
+                    track.last_events[0] = Some((0, device_id));
+                    instr.send_event(Event::new(0, track_id, 0, note));
+                }
+                EngineCommand::AddEffect(track_id, idx, device_id, effect) => {
+                    let track = self.track_mut(track_id);
+                    let idx = usize::min(idx, track.effects.len());
+                    track.effects.insert(idx, Insert::new(device_id, effect));
+                }
+                EngineCommand::RemoveEffect(track_id, device_id) => {
+                    let track = self.track_mut(track_id);
+                    track.effects.retain(|insert| insert.id != device_id);
+                }
+                EngineCommand::SetSidechain(track_id, device_id, key) => {
+                    let track = self.track_mut(track_id);
+                    if let Some(pos) = track.effect_position(device_id) {
+                        track.effects[pos].sidechain = key;
+                    }
+                }
+                EngineCommand::MoveEffect(track_id, device_id, idx) => {
+                    let track = self.track_mut(track_id);
+                    if let Some(pos) = track.effect_position(device_id) {
+                        let insert = track.effects.remove(pos);
+                        let idx = usize::min(idx, track.effects.len());
+                        track.effects.insert(idx, insert);
+                    }
+                }
+                EngineCommand::BypassEffect(track_id, device_id, bypass) => {
+                    let track = self.track_mut(track_id);
+                    if let Some(pos) = track.effect_position(device_id) {
+                        track.effects[pos].bypass = bypass;
+                    }
                 }
             }
         }
@@ -202,11 +461,26 @@ impl Default for Track {
 
 pub struct Track {
     pub buf: Buffer,
-    pub rms_out: Arc<[AtomicF64; 2]>,
+    pub meters: Arc<Meters>,
+    /// Output of the track after volume and mute for the most recently processed buffer
+    out: Buffer,
+    /// Output of the track after the insert effects, before volume and mute are applied. This
+    /// is used as the input for sidechains.
+    pre_fader: Buffer,
     rms: Rms,
-    /// Time and instrument id for the last note on event played on this track. This allows sending
-    /// a note off to that device when a new event is played on this track.
-    last_event: Option<(u64, DeviceId)>,
+    peak: Peak,
+    /// Time and instrument id for the last note on event played in each note column of this
+    /// track. This allows sending a note off to that device when a new event is played in the
+    /// same column.
+    last_events: [Option<(u64, DeviceId)>; MAX_NOTE_COLUMNS],
+    /// Set when the trigger condition or probability of the current step failed, which skips
+    /// all events of the step
+    skip_line: bool,
+    /// Insert effects, processed in order before volume and mute are applied
+    effects: Vec<Insert>,
+    /// Gain applied together with mute, which fades out the track when it's silenced by solo
+    solo_gain: f64,
+    solo_smoothing: params::ExpSmoothing,
 
     params: Arc<TrackParams>,
 }
@@ -215,6 +489,12 @@ pub struct Track {
 pub struct TrackParams {
     volume: Param,
     mute: Param,
+    pan: Param,
+    width: Param,
+    send_a: Param,
+    send_b: Param,
+    send_a_pre: Param,
+    send_b_pre: Param,
 }
 
 impl TrackParams {
@@ -231,17 +511,85 @@ impl TrackParams {
                 1.0,
                 ParamInfo::bool("Mute", 0.0).with_smoothing(params::ExpSmoothing::default()),
             ),
+            pan: Param::new(
+                0.0,
+                ParamInfo::new("Pan", -1.0, 1.0)
+                    .with_steps([0.01, 0.1])
+                    .with_smoothing(params::ExpSmoothing::default())
+                    .with_formatter(format_pan),
+            ),
+            width: Param::new(
+                1.0,
+                ParamInfo::new("Width", 0.0, 2.0)
+                    .with_steps([0.01, 0.1])
+                    .with_smoothing(params::ExpSmoothing::default())
+                    .with_formatter(|width| format!("{:.0}%", width * 100.0)),
+            ),
+            send_a: send_param("Send A"),
+            send_b: send_param("Send B"),
+            send_a_pre: Param::new(0.0, ParamInfo::bool("Send A Pre-fader", 1.0)),
+            send_b_pre: Param::new(0.0, ParamInfo::bool("Send B Pre-fader", 1.0)),
         }
     }
+
+    pub const SENDS: [usize; NUM_SENDS] = [Self::SEND_A, Self::SEND_B];
+    pub const SENDS_PRE: [usize; NUM_SENDS] = [Self::SEND_A_PRE, Self::SEND_B_PRE];
+}
+
+fn format_pan(pan: f64) -> String {
+    let amount = (pan.abs() * 100.0).round();
+    if amount == 0.0 {
+        String::from("C")
+    } else if pan < 0.0 {
+        format!("L{}", amount)
+    } else {
+        format!("R{}", amount)
+    }
+}
+
+const MIN_SEND_LEVEL: f64 = -60.0;
+
+/// Send levels in dB, where the lowest level turns the send off
+fn send_param(name: &str) -> Param {
+    Param::new(
+        MIN_SEND_LEVEL,
+        ParamInfo::new(name, MIN_SEND_LEVEL, 6.0)
+            .with_steps([0.25, 1.0])
+            .with_smoothing(params::ExpSmoothing::default())
+            .with_map(|db| {
+                if db <= MIN_SEND_LEVEL {
+                    0.0
+                } else {
+                    params::db_to_amp(db)
+                }
+            })
+            .with_formatter(|db| {
+                if db <= MIN_SEND_LEVEL {
+                    String::from("off")
+                } else {
+                    format!("{:.2}", db)
+                }
+            }),
+    )
 }
 
 impl Track {
     pub fn new() -> Self {
         Self {
             rms: Rms::new(RMS_WINDOW_SIZE),
-            rms_out: Arc::new([AtomicF64::new(0.0), AtomicF64::new(0.0)]),
+            peak: Peak::new(
+                (SAMPLE_RATE * PEAK_HOLD_SECONDS) as usize,
+                params::db_to_amp(-PEAK_DECAY_DB_PER_SECOND / SAMPLE_RATE) as f32,
+            ),
+            meters: Arc::new(Meters::default()),
             buf: vec![Stereo::ZERO; INTERNAL_BUFFER_SIZE],
-            last_event: None,
+            out: vec![Stereo::ZERO; INTERNAL_BUFFER_SIZE],
+            pre_fader: vec![Stereo::ZERO; INTERNAL_BUFFER_SIZE],
+            last_events: [None; MAX_NOTE_COLUMNS],
+            skip_line: false,
+            effects: Vec::with_capacity(MAX_EFFECTS),
+            solo_gain: 1.0,
+            solo_smoothing: params::ExpSmoothing::default(),
             params: Arc::new(TrackParams::new()),
         }
     }
@@ -250,17 +598,81 @@ impl Track {
         self.params.clone()
     }
 
-    fn process(&mut self, buf: &mut [Stereo]) {
+    fn effect_position(&self, device_id: DeviceId) -> Option<usize> {
+        self.effects
+            .iter()
+            .position(|insert| insert.id == device_id)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.effects.iter().all(|insert| insert.is_idle())
+    }
+
+    fn process(
+        &mut self,
+        ctx: &EffectContext,
+        tracks: &HashMap<TrackId, basedrop::Owned<Track>>,
+        buf: &mut [Stereo],
+        sends: &mut [Buffer; NUM_SENDS],
+        silenced: bool,
+    ) {
+        let num_frames = buf.len();
+        for insert in &mut self.effects {
+            let sidechain = insert
+                .sidechain
+                .and_then(|id| tracks.get(&id))
+                .map(|track| &track.pre_fader[..num_frames]);
+            let ctx = EffectContext { sidechain, ..*ctx };
+            insert.process(&ctx, &mut self.buf[..num_frames]);
+        }
+        self.pre_fader[..num_frames].copy_from_slice(&self.buf[..num_frames]);
+        let sends_pre = TrackParams::SENDS_PRE.map(|idx| self.params.get_param(idx).as_bool());
+        let mut clipped = false;
         for (i, out) in buf.iter_mut().enumerate() {
-            let frame =
-                self.buf[i] * self.params.volume.value() as f32 * self.params.mute.value() as f32;
+            let target = if silenced { 0.0 } else { 1.0 };
+            self.solo_gain = self.solo_smoothing.next(self.solo_gain, target);
+            let mute = self.params.mute.value() * self.solo_gain;
+            let pre_fader = self.buf[i] * mute as f32;
+            let frame = (pre_fader * self.params.volume.value() as f32)
+                .with_width(self.params.width.value() as f32)
+                .pan(self.params.pan.value() as f32);
+            for (send, param) in TrackParams::SENDS.iter().enumerate() {
+                let src = if sends_pre[send] { pre_fader } else { frame };
+                sends[send][i] = src * self.params.get_param(*param).value() as f32;
+            }
             self.rms.add_frame(frame);
+            self.peak.add_frame(frame);
+            clipped |= frame.channel(0).abs() > 1.0 || frame.channel(1).abs() > 1.0;
+            self.out[i] = frame;
             *out += frame;
             self.buf[i] = Stereo::ZERO;
         }
-        let v = self.rms.value().to_db();
-        self.rms_out[0].store(v.channel(0) as f64, Ordering::Relaxed);
-        self.rms_out[1].store(v.channel(1) as f64, Ordering::Relaxed);
+        let rms = self.rms.value().to_db();
+        let peak = self.peak.value().to_db();
+        for ch in 0..2 {
+            self.meters.rms[ch].store(rms.channel(ch) as f64, Ordering::Relaxed);
+            self.meters.peak[ch].store(peak.channel(ch) as f64, Ordering::Relaxed);
+        }
+        if clipped {
+            self.meters.clipped.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Output levels of a track, written by the engine and read by the UI
+#[derive(Default)]
+pub struct Meters {
+    /// RMS level per channel in dB
+    pub rms: [AtomicF64; 2],
+    /// Peak level per channel in dB, held for a moment before it decays
+    pub peak: [AtomicF64; 2],
+    /// Set when the output went over 0 dBFS. It stays set until it's reset from the UI.
+    pub clipped: AtomicBool,
+}
+
+fn mix(dst: &mut [Stereo], src: &[Stereo]) {
+    for (dst, src) in dst.iter_mut().zip(src) {
+        *dst += *src;
     }
 }
 
@@ -304,6 +716,40 @@ impl Device {
     }
 }
 
+/// An effect in the insert chain of a track
+struct Insert {
+    id: DeviceId,
+    effect: basedrop::Owned<Box<dyn Effect + Send>>,
+    bypass: bool,
+    /// Track whose pre-fader output is passed to the effect as sidechain input
+    sidechain: Option<TrackId>,
+    status: Option<ProcessStatus>,
+}
+
+impl Insert {
+    fn new(id: DeviceId, effect: basedrop::Owned<Box<dyn Effect + Send>>) -> Self {
+        Self {
+            id,
+            effect,
+            bypass: false,
+            sidechain: None,
+            status: None,
+        }
+    }
+
+    fn process(&mut self, ctx: &EffectContext, buf: &mut [Stereo]) {
+        if self.bypass {
+            self.status = None;
+            return;
+        }
+        self.status = Some(self.effect.process(ctx, buf));
+    }
+
+    fn is_idle(&self) -> bool {
+        self.bypass || matches!(self.status, Some(ProcessStatus::Idle))
+    }
+}
+
 pub enum ProcessStatus {
     Continue,
     Idle,
@@ -315,19 +761,57 @@ pub trait Plugin {
     fn send_event(&mut self, event: Event);
 }
 
+/// Effects process the audio of a track in place. An effect should return
+/// `ProcessStatus::Continue` as long as it's still producing a tail, e.g. from a delay line.
+pub trait Effect {
+    fn process(&mut self, ctx: &EffectContext, buf: &mut [Stereo]) -> ProcessStatus;
+    fn params(&self) -> Arc<dyn Params>;
+
+    /// Current gain reduction in dB, for effects that reduce dynamics
+    fn gain_reduction(&self) -> Option<Arc<AtomicF64>> {
+        None
+    }
+}
+
+/// Data passed to an effect for processing a single audio buffer
+#[derive(Clone, Copy)]
+pub struct EffectContext<'a> {
+    pub bpm: u16,
+    pub lines_per_beat: u16,
+    /// Pre-fader output of the sidechain track selected for the effect, if any
+    pub sidechain: Option<&'a [Stereo]>,
+}
+
+impl EffectContext<'_> {
+    pub fn new(bpm: u16, lines_per_beat: u16) -> Self {
+        Self {
+            bpm,
+            lines_per_beat,
+            sidechain: None,
+        }
+    }
+
+    pub fn samples_per_line(&self) -> f64 {
+        SAMPLE_RATE * 60.0 / (self.bpm as f64 * self.lines_per_beat as f64)
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Event {
     /// offset of the event within the audio buffer
     pub offset: usize,
     pub track_id: TrackId,
+    /// Note column of the track, a note off only releases the notes played in the same column
+    pub column: usize,
     pub note: Note,
 }
 
 impl Event {
-    pub fn new(offset: usize, track_id: TrackId, note: Note) -> Self {
+    pub fn new(offset: usize, track_id: TrackId, column: usize, note: Note) -> Self {
         Self {
             offset,
             track_id,
+            column,
             note,
         }
     }
@@ -336,16 +820,96 @@ impl Event {
 /// Data passed to a device for processing a single audio buffer
 pub struct ProcessContext<'a> {
     pub num_frames: usize,
-    pub tracks: &'a mut HashMap<TrackId, Box<Track>>,
+    pub tracks: &'a mut HashMap<TrackId, basedrop::Owned<Track>>,
 }
 
 impl<'a> ProcessContext<'a> {
-    pub fn new(tracks: &'a mut HashMap<TrackId, Box<Track>>, num_frames: usize) -> Self {
+    pub fn new(
+        tracks: &'a mut HashMap<TrackId, basedrop::Owned<Track>>,
+        num_frames: usize,
+    ) -> Self {
         Self { num_frames, tracks }
     }
 
-    pub fn track_buffer(&mut self, track_id: TrackId, range: &Range<usize>) -> &mut [Stereo] {
-        let track = self.tracks.get_mut(&track_id).unwrap();
-        &mut track.buf[range.clone()]
+    /// Returns the buffer of a track, or None if the track has been deleted
+    pub fn track_buffer(
+        &mut self,
+        track_id: TrackId,
+        range: &Range<usize>,
+    ) -> Option<&mut [Stereo]> {
+        let track = self.tracks.get_mut(&track_id)?;
+        Some(&mut track.buf[range.clone()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{self, Msg};
+    use crate::pattern::Position;
+    use camino::Utf8PathBuf;
+
+    const PASSES: usize = 8;
+
+    /// Plays a pattern with a `P50` step on line 0, a `T24` step on line 1 and a fill step on
+    /// line 2. Returns which of these lines were played on each pass.
+    fn play_conditional_trigs(seed: u64, fill: bool) -> Vec<[bool; 3]> {
+        let (mut app, mut app_state, mut engine, _) = app::new().unwrap();
+        app.send(Msg::CreateTrack(0)).unwrap();
+        app.send(Msg::LoadSound(0, Utf8PathBuf::from("sounds/kick.wav")))
+            .unwrap();
+        app.send(Msg::CreatePattern(None)).unwrap();
+        let msg = app.update_pattern(|p| {
+            p.set_len(4);
+            for (line, (cmd, value)) in [('P', "50"), ('T', "24"), ('T', "3")].iter().enumerate() {
+                p.set_key(Position::new(line, 0, 0), 4, 'z');
+                p.set_key(Position::new(line, 0, 3), 4, *cmd);
+                for key in value.chars() {
+                    p.set_key(Position::new(line, 0, 4), 4, key);
+                }
+            }
+        });
+        app.send(msg).unwrap();
+        app.state.is_playing = true;
+        app.state.fill = fill;
+        app.publish();
+        engine.seed(seed);
+
+        let track_id = app.state.tracks[0].id;
+        // One tick per buffer, see `Engine::tick`
+        let mut buf = [Stereo::ZERO; 459];
+        let mut passes = Vec::new();
+        for _ in 0..PASSES {
+            let mut played = [false; 3];
+            for line in 0..4 {
+                for _ in 0..TICKS_PER_LINE {
+                    engine.process(app_state.read(), &mut buf);
+                    let track = &engine.tracks[&track_id];
+                    let is_triggered =
+                        track.last_events[0].map(|(tick, _)| tick + 1) == Some(engine.total_ticks);
+                    if let (true, Some(played)) = (is_triggered, played.get_mut(line)) {
+                        *played = true;
+                    }
+                }
+            }
+            passes.push(played);
+        }
+        passes
+    }
+
+    #[test]
+    fn conditional_trigs() {
+        let passes = play_conditional_trigs(7, false);
+        assert_eq!(passes, play_conditional_trigs(7, false));
+
+        let probability: Vec<bool> = passes.iter().map(|p| p[0]).collect();
+        assert!(probability.contains(&true) && probability.contains(&false));
+
+        let every: Vec<usize> = (0..PASSES).filter(|pass| passes[*pass][1]).collect();
+        assert_eq!(vec![1, 5], every);
+        assert!(passes.iter().all(|p| !p[2]));
+
+        let passes = play_conditional_trigs(7, true);
+        assert!(passes.iter().all(|p| p[2]));
     }
 }
@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+
+const MAX_UNDO_LEVELS: usize = 200;
+
+/// Undo and redo stacks. Entries describe how to get back to a previous state, so undoing an
+/// entry should produce a new entry that can be used to redo the change.
+pub struct History<T> {
+    undo: VecDeque<T>,
+    redo: Vec<T>,
+}
+
+impl<T> History<T> {
+    pub fn new() -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    /// Record a new change. This invalidates everything that could be redone.
+    pub fn push(&mut self, entry: T) {
+        self.redo.clear();
+        self.push_undo(entry);
+    }
+
+    pub fn push_undo(&mut self, entry: T) {
+        if self.undo.len() >= MAX_UNDO_LEVELS {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(entry);
+    }
+
+    pub fn push_redo(&mut self, entry: T) {
+        self.redo.push(entry);
+    }
+
+    pub fn undo(&mut self) -> Option<T> {
+        self.undo.pop_back()
+    }
+
+    pub fn redo(&mut self) -> Option<T> {
+        self.redo.pop()
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.undo.back()
+    }
+
+    pub fn clear_redo(&mut self) {
+        self.redo.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_redo() {
+        let mut history = History::new();
+        let mut state = 0;
+
+        for i in 1..=3 {
+            history.push(state);
+            state = i;
+        }
+
+        let undo = |history: &mut History<i32>, state: &mut i32| {
+            let prev = history.undo().unwrap();
+            history.push_redo(*state);
+            *state = prev;
+        };
+        undo(&mut history, &mut state);
+        undo(&mut history, &mut state);
+        assert_eq!(1, state);
+
+        let next = history.redo().unwrap();
+        history.push_undo(state);
+        state = next;
+        assert_eq!(2, state);
+
+        history.push(state);
+        assert!(history.redo().is_none());
+        assert_eq!(Some(&2), history.last());
+    }
+
+    #[test]
+    fn max_undo_levels() {
+        let mut history = History::new();
+        for i in 0..MAX_UNDO_LEVELS + 10 {
+            history.push(i);
+        }
+        let mut count = 0;
+        while history.undo().is_some() {
+            count += 1;
+        }
+        assert_eq!(MAX_UNDO_LEVELS, count);
+    }
+}
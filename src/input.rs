@@ -2,20 +2,23 @@ use anyhow::{anyhow, Result};
 use camino::Utf8PathBuf;
 use ratatui::{
     crossterm::event::{KeyCode, KeyEvent, KeyModifiers},
+    style::Color,
     widgets::ListState,
 };
 
-use crate::app::{App, Msg};
-use crate::engine::TrackParams;
-use crate::pattern::{Selection, StepSize, INPUTS_PER_STEP};
+use crate::app::{random_color, App, DeviceId, Msg};
+use crate::effects::EffectType;
+use crate::engine::{TrackParams, NUM_SENDS};
+use crate::pattern::{Selection, StepSize};
+use crate::render::RenderOptions;
 use crate::sampler;
-use crate::view::{Focus, ProjectTreeState, View};
+use crate::view::{self, Focus, ProjectTreeState, RenameTarget, View};
 
 pub fn handle_key_event(app: &App, view: &mut View, key: KeyEvent) -> Msg {
     match handle_key(app, view, key) {
         Ok(msg) => msg,
         Err(err) => {
-            eprintln!("error: {}", err);
+            view.message = Some(format!("error: {}", err));
             Msg::Noop
         }
     }
@@ -24,6 +27,12 @@ pub fn handle_key_event(app: &App, view: &mut View, key: KeyEvent) -> Msg {
 fn handle_key(app: &App, view: &mut View, key: KeyEvent) -> Result<Msg> {
     use Msg::*;
 
+    view.message = None;
+
+    if view.rename.is_some() {
+        return Ok(handle_rename_input(view, key));
+    }
+
     if key.code == KeyCode::Char('w') && key.modifiers.contains(KeyModifiers::CONTROL) {
         use Focus::*;
         view.focus = match view.focus {
@@ -41,6 +50,15 @@ fn handle_key(app: &App, view: &mut View, key: KeyEvent) -> Result<Msg> {
         return Ok(Noop);
     }
 
+    if key.code == KeyCode::Char('z') && view.focus != Focus::CommandLine {
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            return Ok(Undo);
+        }
+        if key.modifiers.contains(KeyModifiers::ALT) {
+            return Ok(Redo);
+        }
+    }
+
     match view.focus {
         Focus::Editor => return handle_editor_input(app, view, key),
         Focus::CommandLine => return handle_command_line_input(app, view, key),
@@ -58,6 +76,11 @@ fn handle_key(app: &App, view: &mut View, key: KeyEvent) -> Result<Msg> {
             KeyCode::Char('l') => return Ok(LoopToggle(view.patterns.selected().unwrap())),
             KeyCode::Char('L') => return Ok(LoopAdd(view.patterns.selected().unwrap())),
             KeyCode::Enter => return Ok(SelectPattern(view.patterns.selected().unwrap())),
+            KeyCode::Char('r') => {
+                let idx = view.patterns.selected().unwrap();
+                let name = app.state.song_iter().nth(idx).and_then(|p| p.name.clone());
+                view.rename = Some((RenameTarget::Pattern(idx), name.unwrap_or_default()));
+            }
             _ => handle_list_input(&mut view.patterns, key),
         },
         Focus::ProjectTree => return handle_project_tree_input(app, view, key),
@@ -117,6 +140,18 @@ fn handle_editor_input(app: &App, view: &mut View, key: KeyEvent) -> Result<Msg>
                 view.selection = None;
                 return Ok(Noop);
             }
+            KeyCode::Char('m') if key.modifiers.contains(KeyModifiers::ALT) => {
+                // Mute all selected tracks, or unmute them if they're all muted already
+                let tracks: Vec<usize> = s.tracks().collect();
+                let mute = tracks.iter().any(|idx| {
+                    let track = &app.state.tracks[*idx];
+                    !app.params(track.device_id)
+                        .get_param(TrackParams::MUTE)
+                        .as_bool()
+                });
+                view.selection = None;
+                return Ok(SetMute(tracks, mute));
+            }
             _ => {}
         }
     }
@@ -131,11 +166,20 @@ fn handle_editor_input(app: &App, view: &mut View, key: KeyEvent) -> Result<Msg>
 
     match key.code {
         KeyCode::Char('m') if key.modifiers.contains(KeyModifiers::ALT) => {
-            let track = &app.state.tracks[view.editor.cursor.track()];
+            let track = &app.state.tracks[view.editor.cursor.track];
             return Ok(ParamToggle(track.device_id, TrackParams::MUTE));
         }
+        KeyCode::Char('o') if key.modifiers.contains(KeyModifiers::ALT) => {
+            return Ok(Solo(view.editor.cursor.track, false));
+        }
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::ALT) => {
+            return Ok(ResetClipIndicators);
+        }
+        KeyCode::Char('O') if key.modifiers.contains(KeyModifiers::ALT) => {
+            return Ok(Solo(view.editor.cursor.track, true));
+        }
         KeyCode::Char('=') if key.modifiers.contains(KeyModifiers::ALT) => {
-            let track = &app.state.tracks[view.editor.cursor.track()];
+            let track = &app.state.tracks[view.editor.cursor.track];
             return Ok(ParamInc(
                 track.device_id,
                 TrackParams::VOLUME,
@@ -143,22 +187,75 @@ fn handle_editor_input(app: &App, view: &mut View, key: KeyEvent) -> Result<Msg>
             ));
         }
         KeyCode::Char('-') if key.modifiers.contains(KeyModifiers::ALT) => {
-            let track = &app.state.tracks[view.editor.cursor.track()];
+            let track = &app.state.tracks[view.editor.cursor.track];
             return Ok(ParamDec(
                 track.device_id,
                 TrackParams::VOLUME,
                 StepSize::Large,
             ));
         }
+        KeyCode::Char(',') if key.modifiers.contains(KeyModifiers::ALT) => {
+            let track = &app.state.tracks[view.editor.cursor.track];
+            return Ok(ParamDec(track.device_id, TrackParams::PAN, StepSize::Large));
+        }
+        KeyCode::Char('.') if key.modifiers.contains(KeyModifiers::ALT) => {
+            let track = &app.state.tracks[view.editor.cursor.track];
+            return Ok(ParamInc(track.device_id, TrackParams::PAN, StepSize::Large));
+        }
+        KeyCode::Char('<') if key.modifiers.contains(KeyModifiers::ALT) => {
+            let track = &app.state.tracks[view.editor.cursor.track];
+            return Ok(ParamDec(
+                track.device_id,
+                TrackParams::WIDTH,
+                StepSize::Large,
+            ));
+        }
+        KeyCode::Char('>') if key.modifiers.contains(KeyModifiers::ALT) => {
+            let track = &app.state.tracks[view.editor.cursor.track];
+            return Ok(ParamInc(
+                track.device_id,
+                TrackParams::WIDTH,
+                StepSize::Large,
+            ));
+        }
+        // Sends use the same keys as the volume with shift, Alt-1 and Alt-2 pick the send
+        KeyCode::Char(c @ '1'..='9') if key.modifiers.contains(KeyModifiers::ALT) => {
+            let send_idx = c as usize - '1' as usize;
+            if send_idx < NUM_SENDS {
+                view.editor.send = send_idx;
+            }
+            return Ok(Noop);
+        }
+        KeyCode::Char('+') if key.modifiers.contains(KeyModifiers::ALT) => {
+            let track = &app.state.tracks[view.editor.cursor.track];
+            return Ok(ParamInc(
+                track.device_id,
+                TrackParams::SENDS[view.editor.send],
+                StepSize::Large,
+            ));
+        }
+        KeyCode::Char('_') if key.modifiers.contains(KeyModifiers::ALT) => {
+            let track = &app.state.tracks[view.editor.cursor.track];
+            return Ok(ParamDec(
+                track.device_id,
+                TrackParams::SENDS[view.editor.send],
+                StepSize::Large,
+            ));
+        }
         KeyCode::Char('v') if key.modifiers.contains(KeyModifiers::CONTROL) => {
             let pos = view.editor.cursor;
             view.selection = Some(Selection::new(pos, pos));
             return Ok(Noop);
         }
         KeyCode::Char(' ') => return Ok(TogglePlay),
+        KeyCode::Char('l') if key.modifiers.contains(KeyModifiers::ALT) => return Ok(ToggleFill),
         KeyCode::Backspace => {
             let msg = app.update_pattern(|p| p.clear(view.editor.cursor));
-            if view.editor.cursor.is_pitch_input() {
+            if app
+                .state
+                .selected_pattern()
+                .is_pitch_input(view.editor.cursor)
+            {
                 move_editor_cursor(app, view, CursorMove::Down);
             }
             return Ok(msg);
@@ -214,7 +311,11 @@ fn handle_editor_input(app: &App, view: &mut View, key: KeyEvent) -> Result<Msg>
         KeyCode::Char(key) => {
             let msg =
                 app.update_pattern(|p| p.set_key(view.editor.cursor, app.state.octave as u8, key));
-            if view.editor.cursor.is_pitch_input() {
+            if app
+                .state
+                .selected_pattern()
+                .is_pitch_input(view.editor.cursor)
+            {
                 move_editor_cursor(app, view, CursorMove::Down)
             }
             return Ok(msg);
@@ -245,7 +346,92 @@ fn handle_command_line_input(app: &App, view: &mut View, key: KeyEvent) -> Resul
                     Ok(SetOct(oct))
                 }
                 "bpm" => Ok(SetBpm(parts[1].parse()?)),
+                "fill" => Ok(ToggleFill),
                 "quit" | "q" | "exit" => Ok(Exit),
+                "undo" => Ok(Undo),
+                "redo" => Ok(Redo),
+                "w" | "write" => {
+                    let path = parts
+                        .get(1)
+                        .map(|p| Utf8PathBuf::from(*p))
+                        .or_else(|| app.project_path.clone())
+                        .ok_or_else(|| anyhow!("write: no file name"))?;
+                    Ok(SaveProject(path))
+                }
+                "e" | "edit" if parts.len() == 2 => Ok(LoadProject(Utf8PathBuf::from(parts[1]))),
+                "render" if parts.len() >= 2 => {
+                    let options = RenderOptions::parse(&parts[2..])?;
+                    view.message = Some(format!("rendering {}", parts[1]));
+                    Ok(Render(Utf8PathBuf::from(parts[1]), options))
+                }
+                "stems" if parts.len() >= 2 => {
+                    let options = RenderOptions::parse(&parts[2..])?;
+                    view.message = Some(format!("rendering stems to {}", parts[1]));
+                    Ok(RenderStems(Utf8PathBuf::from(parts[1]), options))
+                }
+                "loudness" => {
+                    let loudness = &app.engine_state.loudness;
+                    view.message = Some(format!(
+                        "integrated {} LUFS, range {:.1} LU, short-term {} LUFS, \
+                         momentary {} LUFS, true peak {} dBTP",
+                        view::format_lufs(loudness.integrated),
+                        loudness.range,
+                        view::format_lufs(loudness.short_term),
+                        view::format_lufs(loudness.momentary),
+                        view::format_lufs(loudness.true_peak),
+                    ));
+                    Ok(Noop)
+                }
+                "addtrack" => {
+                    let num_tracks = app.state.tracks.iter().filter(|t| !t.is_bus()).count();
+                    Ok(CreateTrack(num_tracks))
+                }
+                "inserttrack" => Ok(CreateTrack(view.editor.cursor.track)),
+                "deltrack" => {
+                    let idx = match parts.get(1) {
+                        Some(idx) => idx.parse()?,
+                        None => view.editor.cursor.track,
+                    };
+                    if idx >= app.state.tracks.len() {
+                        return Err(anyhow!("deltrack: invalid track {}", idx));
+                    }
+                    // Keep the selection in the track list within bounds
+                    if view
+                        .tracks
+                        .selected()
+                        .is_some_and(|i| i + 2 >= app.state.tracks.len())
+                    {
+                        view.tracks.select(Some(app.state.tracks.len() - 2));
+                    }
+                    // Track indices after the deleted track shift, so leave the devices of a track
+                    if !matches!(
+                        view.project_tree_state,
+                        ProjectTreeState::Instruments | ProjectTreeState::InstrumentParams(_)
+                    ) {
+                        view.project_tree_state = ProjectTreeState::Tracks;
+                    }
+                    Ok(DeleteTrack(idx))
+                }
+                "name" if parts.len() >= 2 => {
+                    let name = parts[2..].join(" ");
+                    match parts[1] {
+                        "track" => Ok(RenameTrack(view.editor.cursor.track, name)),
+                        "instr" => Ok(RenameInstrument(view.instruments.selected().unwrap(), name)),
+                        "pattern" => Ok(RenamePattern(app.state.selected_pattern, name)),
+                        _ => Err(anyhow!("name: expected track, instr or pattern")),
+                    }
+                }
+                "color" => {
+                    let color = match parts.get(1) {
+                        Some(&"random") => Some(random_color()),
+                        Some(color) => Some(parse_color(color)?),
+                        None => None,
+                    };
+                    Ok(SetTrackColor(view.editor.cursor.track, color))
+                }
+                "columns" if parts.len() == 2 => {
+                    Ok(SetNoteColumns(view.editor.cursor.track, parts[1].parse()?))
+                }
                 "setlength" if parts.len() == 2 => {
                     let new_length = parts[1].parse()?;
                     Ok(app.update_pattern(|p| p.set_len(new_length)))
@@ -282,6 +468,42 @@ fn handle_command_line_input(app: &App, view: &mut View, key: KeyEvent) -> Resul
     Ok(Noop)
 }
 
+fn handle_rename_input(view: &mut View, key: KeyEvent) -> Msg {
+    use Msg::*;
+    let Some((target, name)) = &mut view.rename else {
+        return Noop;
+    };
+    match key.code {
+        KeyCode::Enter => {
+            let name = name.trim().to_string();
+            let msg = match *target {
+                RenameTarget::Track(idx) => RenameTrack(idx, name),
+                RenameTarget::Instrument(idx) => RenameInstrument(idx, name),
+                RenameTarget::Pattern(idx) => RenamePattern(idx, name),
+            };
+            view.rename = None;
+            return msg;
+        }
+        KeyCode::Esc => view.rename = None,
+        KeyCode::Backspace => {
+            name.pop();
+        }
+        KeyCode::Char(c) => name.push(c),
+        _ => {}
+    }
+    Noop
+}
+
+/// Parses a color in the `#rrggbb` format
+fn parse_color(s: &str) -> Result<Color> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 {
+        return Err(anyhow!("invalid color {}", s));
+    }
+    let rgb = u32::from_str_radix(hex, 16).map_err(|_| anyhow!("invalid color {}", s))?;
+    Ok(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
 fn handle_project_tree_input(app: &App, view: &mut View, key: KeyEvent) -> Result<Msg> {
     use Msg::*;
     match key.code {
@@ -302,63 +524,212 @@ fn handle_project_tree_input(app: &App, view: &mut View, key: KeyEvent) -> Resul
                     view.project_tree_state =
                         ProjectTreeState::Devices(view.tracks.selected().unwrap())
                 }
+                KeyCode::Char('a') => return Ok(CreateBus),
+                KeyCode::Char('S') => return Ok(ToggleSoloSafe(view.tracks.selected().unwrap())),
+                KeyCode::Char('r') => {
+                    let idx = view.tracks.selected().unwrap();
+                    let name = app.state.tracks[idx].name.clone().unwrap_or_default();
+                    view.rename = Some((RenameTarget::Track(idx), name));
+                }
+                KeyCode::Char('c') => {
+                    let idx = view.tracks.selected().unwrap();
+                    return Ok(SetTrackColor(idx, Some(random_color())));
+                }
+                KeyCode::Char('C') => {
+                    return Ok(SetTrackColor(view.tracks.selected().unwrap(), None))
+                }
+                KeyCode::Char(c @ ('o' | 'O')) if key.modifiers.contains(KeyModifiers::ALT) => {
+                    return Ok(Solo(view.tracks.selected().unwrap(), c == 'O'));
+                }
+                KeyCode::Char('o') => {
+                    // Cycle the output of the track through the buses it can be routed to
+                    let track_idx = view.tracks.selected().unwrap();
+                    let tracks = &app.state.tracks;
+                    let track = &tracks[track_idx];
+                    if track_idx == tracks.len() - 1 {
+                        return Ok(Noop);
+                    }
+                    let buses: Vec<_> = tracks[..tracks.len() - 1]
+                        .iter()
+                        .filter(|bus| bus.is_bus() && !app.state.routes_to(bus.id, track.id))
+                        .map(|bus| bus.id)
+                        .collect();
+                    let next = match track.output {
+                        Some(id) => buses
+                            .iter()
+                            .position(|bus| *bus == id)
+                            .and_then(|pos| buses.get(pos + 1))
+                            .copied(),
+                        None => buses.first().copied(),
+                    };
+                    return Ok(SetOutput(track_idx, next));
+                }
+                KeyCode::Char(c @ '1'..='9') => {
+                    let send_idx = c as usize - '1' as usize;
+                    let track_idx = view.tracks.selected().unwrap();
+                    let tracks = &app.state.tracks;
+                    let track = &tracks[track_idx];
+                    if send_idx >= NUM_SENDS || track.is_bus() {
+                        return Ok(Noop);
+                    }
+                    if key.modifiers.contains(KeyModifiers::ALT) {
+                        return Ok(ParamToggle(
+                            track.device_id,
+                            TrackParams::SENDS_PRE[send_idx],
+                        ));
+                    }
+                    // Cycle the send through the return buses
+                    let buses: Vec<_> = tracks[..tracks.len() - 1]
+                        .iter()
+                        .filter(|bus| bus.is_bus())
+                        .map(|bus| bus.id)
+                        .collect();
+                    let next = match track.sends[send_idx] {
+                        Some(id) => buses
+                            .iter()
+                            .position(|bus| *bus == id)
+                            .and_then(|pos| buses.get(pos + 1))
+                            .copied(),
+                        None => buses.first().copied(),
+                    };
+                    return Ok(SetSend(track_idx, send_idx, next));
+                }
                 _ => handle_list_input(&mut view.tracks, key),
             };
         }
         ProjectTreeState::InstrumentParams(instr_idx) => {
-            let device_id = app.state.instruments[instr_idx].as_ref().unwrap().id;
+            let Some(instrument) = app
+                .state
+                .instruments
+                .get(instr_idx)
+                .and_then(Option::as_ref)
+            else {
+                view.project_tree_state = ProjectTreeState::Instruments;
+                return Ok(Noop);
+            };
+            let device_id = instrument.id;
             match key.code {
                 KeyCode::Char('u') => {
                     view.project_tree_state = ProjectTreeState::Instruments;
                 }
-                KeyCode::Char('[') => {
-                    return Ok(ParamInc(
-                        device_id,
-                        view.params.selected().unwrap(),
-                        StepSize::Default,
-                    ))
-                }
-                KeyCode::Char(']') => {
-                    return Ok(ParamDec(
-                        device_id,
-                        view.params.selected().unwrap(),
-                        StepSize::Default,
-                    ))
-                }
-                KeyCode::Char('{') => {
-                    return Ok(ParamInc(
-                        device_id,
-                        view.params.selected().unwrap(),
-                        StepSize::Large,
-                    ))
-                }
-                KeyCode::Char('}') => {
-                    return Ok(ParamDec(
-                        device_id,
-                        view.params.selected().unwrap(),
-                        StepSize::Large,
-                    ))
-                }
-                _ => handle_list_input(&mut view.params, key),
+                _ => return Ok(handle_params_input(device_id, view, key)),
             };
         }
-        ProjectTreeState::Devices(_track_idx) => {
+        ProjectTreeState::Devices(track_idx) => {
+            let Some(track) = app.state.tracks.get(track_idx) else {
+                view.project_tree_state = ProjectTreeState::Tracks;
+                return Ok(Noop);
+            };
+            let effects = &track.effects;
+            let device_idx = view.devices.selected().unwrap_or(0);
             match key.code {
                 KeyCode::Char('u') => {
                     view.project_tree_state = ProjectTreeState::Tracks;
                 }
+                KeyCode::Char('a') => {
+                    view.project_tree_state = ProjectTreeState::AddDevice(track_idx);
+                }
+                KeyCode::Enter if device_idx < effects.len() => {
+                    view.params.select(Some(0));
+                    view.project_tree_state = ProjectTreeState::DeviceParams(track_idx, device_idx);
+                }
+                KeyCode::Backspace => return Ok(RemoveEffect(track_idx, device_idx)),
+                KeyCode::Char('b') => return Ok(ToggleBypass(track_idx, device_idx)),
+                KeyCode::Up if key.modifiers.contains(KeyModifiers::ALT) => {
+                    if device_idx > 0 && device_idx < effects.len() {
+                        view.devices.select(Some(device_idx - 1));
+                        return Ok(MoveEffect(track_idx, device_idx, device_idx - 1));
+                    }
+                }
+                KeyCode::Down if key.modifiers.contains(KeyModifiers::ALT) => {
+                    if device_idx + 1 < effects.len() {
+                        view.devices.select(Some(device_idx + 1));
+                        return Ok(MoveEffect(track_idx, device_idx, device_idx + 1));
+                    }
+                }
                 _ => handle_list_input(&mut view.devices, key),
             };
         }
+        ProjectTreeState::AddDevice(track_idx) => {
+            match key.code {
+                KeyCode::Char('u') | KeyCode::Esc => {
+                    view.project_tree_state = ProjectTreeState::Devices(track_idx);
+                }
+                KeyCode::Enter => {
+                    let Some(track) = app.state.tracks.get(track_idx) else {
+                        view.project_tree_state = ProjectTreeState::Tracks;
+                        return Ok(Noop);
+                    };
+                    let effect_type = EffectType::ALL[view.effect_types.selected().unwrap()];
+                    view.devices.select(Some(track.effects.len()));
+                    view.project_tree_state = ProjectTreeState::Devices(track_idx);
+                    return Ok(AddEffect(track_idx, effect_type));
+                }
+                _ => handle_list_input(&mut view.effect_types, key),
+            };
+        }
+        ProjectTreeState::DeviceParams(track_idx, device_idx) => {
+            // The device can disappear from under us, e.g. when loading a project
+            let Some(track) = app.state.tracks.get(track_idx) else {
+                view.project_tree_state = ProjectTreeState::Tracks;
+                return Ok(Noop);
+            };
+            let Some(device) = track.effects.get(device_idx) else {
+                view.project_tree_state = ProjectTreeState::Devices(track_idx);
+                return Ok(Noop);
+            };
+            let device_id = device.id;
+            match key.code {
+                KeyCode::Char('u') => {
+                    view.project_tree_state = ProjectTreeState::Devices(track_idx);
+                }
+                KeyCode::Char('k') if device.effect_type.has_sidechain() => {
+                    // Cycle through the other instrument tracks as sidechain input
+                    let tracks = &app.state.tracks;
+                    let candidates: Vec<_> = tracks
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, track)| *i != track_idx && !track.is_bus())
+                        .map(|(_, track)| track.id)
+                        .collect();
+                    let next = match device.sidechain {
+                        Some(id) => candidates
+                            .iter()
+                            .position(|c| *c == id)
+                            .and_then(|pos| candidates.get(pos + 1))
+                            .copied(),
+                        None => candidates.first().copied(),
+                    };
+                    return Ok(SetSidechain(track_idx, device_idx, next));
+                }
+                _ => return Ok(handle_params_input(device_id, view, key)),
+            };
+        }
         ProjectTreeState::Instruments => {
             match key.code {
                 KeyCode::Enter => {
                     let idx = view.instruments.selected().unwrap();
-                    if app.state.instruments[idx].is_some() {
+                    if app.state.instruments.get(idx).is_some_and(Option::is_some) {
                         view.project_tree_state = ProjectTreeState::InstrumentParams(idx);
                     }
                 }
                 KeyCode::Char('l') => view.focus = Focus::FileLoader,
+                KeyCode::Char('a') => return Ok(CreateInstrument(app.state.instruments.len())),
+                KeyCode::Char('i') => {
+                    return Ok(CreateInstrument(view.instruments.selected().unwrap()))
+                }
+                KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(DuplicateInstrument(view.instruments.selected().unwrap()))
+                }
+                KeyCode::Backspace => {
+                    return Ok(DeleteInstrument(view.instruments.selected().unwrap()))
+                }
+                KeyCode::Char('r') => {
+                    let idx = view.instruments.selected().unwrap();
+                    if let Some(instr) = &app.state.instruments[idx] {
+                        view.rename = Some((RenameTarget::Instrument(idx), instr.name.clone()));
+                    }
+                }
                 _ => handle_list_input(&mut view.instruments, key),
             };
         }
@@ -366,6 +737,20 @@ fn handle_project_tree_input(app: &App, view: &mut View, key: KeyEvent) -> Resul
     Ok(Noop)
 }
 
+fn handle_params_input(device_id: DeviceId, view: &mut View, key: KeyEvent) -> Msg {
+    let param_idx = view.params.selected().unwrap();
+    match key.code {
+        KeyCode::Char('[') => Msg::ParamInc(device_id, param_idx, StepSize::Default),
+        KeyCode::Char(']') => Msg::ParamDec(device_id, param_idx, StepSize::Default),
+        KeyCode::Char('{') => Msg::ParamInc(device_id, param_idx, StepSize::Large),
+        KeyCode::Char('}') => Msg::ParamDec(device_id, param_idx, StepSize::Large),
+        _ => {
+            handle_list_input(&mut view.params, key);
+            Msg::Noop
+        }
+    }
+}
+
 enum CursorMove {
     Up,
     Down,
@@ -392,26 +777,39 @@ fn handle_list_input(list: &mut ListState, key: KeyEvent) {
 fn move_editor_cursor(app: &App, view: &mut View, cursor_move: CursorMove) {
     use CursorMove::*;
 
-    let pattern_size = app.state.selected_pattern().size();
+    let pattern = app.state.selected_pattern();
     let cursor = &mut view.editor.cursor;
+    let last_track = pattern.tracks.len() - 1;
 
     match cursor_move {
         Up => cursor.line = cursor.line.saturating_sub(1),
-        Down => cursor.line = usize::min(pattern_size.lines - 1, cursor.line + 1),
-        Left => cursor.column = cursor.column.saturating_sub(1),
-        Right => cursor.column = usize::min(pattern_size.columns - 1, cursor.column + 1),
-        NextTrack => {
-            let col = cursor.column + INPUTS_PER_STEP;
-            if col <= pattern_size.columns {
-                cursor.column = col;
+        Down => cursor.line = usize::min(pattern.len() - 1, cursor.line + 1),
+        Left => {
+            if cursor.column > 0 {
+                cursor.column -= 1;
+            } else if cursor.track > 0 {
+                cursor.track -= 1;
+                cursor.column = pattern.inputs(cursor.track) - 1;
             }
         }
-        PrevTrack => {
-            if cursor.track() > 0 {
-                cursor.column -= INPUTS_PER_STEP;
+        Right => {
+            if cursor.column + 1 < pattern.inputs(cursor.track) {
+                cursor.column += 1;
+            } else if cursor.track < last_track {
+                cursor.track += 1;
+                cursor.column = 0;
             }
         }
-        LineStart => cursor.column = 0,
-        LineEnd => cursor.column = pattern_size.columns - 1,
+        NextTrack => cursor.track = usize::min(last_track, cursor.track + 1),
+        PrevTrack => cursor.track = cursor.track.saturating_sub(1),
+        LineStart => {
+            cursor.track = 0;
+            cursor.column = 0;
+        }
+        LineEnd => {
+            cursor.track = last_track;
+            cursor.column = pattern.inputs(last_track) - 1;
+        }
     }
+    *cursor = pattern.clamp(*cursor);
 }
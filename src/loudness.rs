@@ -0,0 +1,401 @@
+//! Loudness metering as described in EBU R128, using the measurements from ITU-R BS.1770 and
+//! EBU Tech 3341/3342. Integrated loudness and loudness range are computed from histograms of
+//! the block loudness, so the meter doesn't allocate while it's running.
+
+use std::f64::consts::PI;
+
+use crate::audio::Stereo;
+
+const SUB_BLOCK_SECONDS: f64 = 0.1;
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+const ABSOLUTE_GATE: f64 = -70.0;
+const INTEGRATED_RELATIVE_GATE: f64 = -10.0;
+const RANGE_RELATIVE_GATE: f64 = -20.0;
+const HISTOGRAM_MAX: f64 = 10.0;
+const HISTOGRAM_STEP: f64 = 0.1;
+const HISTOGRAM_BINS: usize = ((HISTOGRAM_MAX - ABSOLUTE_GATE) / HISTOGRAM_STEP) as usize;
+const OVERSAMPLING: usize = 4;
+const INTERPOLATION_TAPS: usize = 12;
+
+/// Loudness values in LUFS, loudness range in LU and true peak in dBTP
+#[derive(Clone, Copy, Debug)]
+pub struct Loudness {
+    pub momentary: f64,
+    pub short_term: f64,
+    pub integrated: f64,
+    pub range: f64,
+    pub true_peak: f64,
+}
+
+impl Default for Loudness {
+    fn default() -> Self {
+        Self {
+            momentary: f64::NEG_INFINITY,
+            short_term: f64::NEG_INFINITY,
+            integrated: f64::NEG_INFINITY,
+            range: 0.0,
+            true_peak: f64::NEG_INFINITY,
+        }
+    }
+}
+
+pub struct LoudnessMeter {
+    /// K-weighting filters per channel: a high shelf followed by a high pass
+    filters: [[Biquad; 2]; 2],
+    sub_block_len: usize,
+    sub_block_pos: usize,
+    sub_block_sum: f64,
+    /// Mean square of the most recent sub-blocks
+    sub_blocks: [f64; SHORT_TERM_SUB_BLOCKS],
+    sub_block_idx: usize,
+    num_sub_blocks: usize,
+    momentary_blocks: Histogram,
+    short_term_blocks: Histogram,
+    true_peak: TruePeak,
+    loudness: Loudness,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: f64) -> Self {
+        let filters = [
+            Biquad::pre_filter(sample_rate),
+            Biquad::rlb_filter(sample_rate),
+        ];
+        Self {
+            filters: [filters, filters],
+            sub_block_len: (sample_rate * SUB_BLOCK_SECONDS).round() as usize,
+            sub_block_pos: 0,
+            sub_block_sum: 0.0,
+            sub_blocks: [0.0; SHORT_TERM_SUB_BLOCKS],
+            sub_block_idx: 0,
+            num_sub_blocks: 0,
+            momentary_blocks: Histogram::new(),
+            short_term_blocks: Histogram::new(),
+            true_peak: TruePeak::new(),
+            loudness: Loudness::default(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for filter in self.filters.iter_mut().flatten() {
+            filter.reset();
+        }
+        self.sub_block_pos = 0;
+        self.sub_block_sum = 0.0;
+        self.num_sub_blocks = 0;
+        self.momentary_blocks.clear();
+        self.short_term_blocks.clear();
+        self.true_peak.reset();
+        self.loudness = Loudness::default();
+    }
+
+    pub fn process(&mut self, buf: &[Stereo]) {
+        for frame in buf {
+            for ch in 0..2 {
+                let sample = frame.channel(ch) as f64;
+                let [pre_filter, rlb_filter] = &mut self.filters[ch];
+                let weighted = rlb_filter.process(pre_filter.process(sample));
+                self.sub_block_sum += weighted * weighted;
+                self.true_peak.add_sample(ch, sample);
+            }
+            self.sub_block_pos += 1;
+            if self.sub_block_pos == self.sub_block_len {
+                self.end_sub_block();
+            }
+        }
+        self.loudness.true_peak = amp_to_db(self.true_peak.value());
+    }
+
+    pub fn value(&self) -> Loudness {
+        self.loudness
+    }
+
+    fn end_sub_block(&mut self) {
+        self.sub_blocks[self.sub_block_idx] = self.sub_block_sum / self.sub_block_len as f64;
+        self.sub_block_idx = (self.sub_block_idx + 1) % SHORT_TERM_SUB_BLOCKS;
+        self.num_sub_blocks += 1;
+        self.sub_block_pos = 0;
+        self.sub_block_sum = 0.0;
+
+        // Blocks overlap, a new momentary and short-term block ends with every sub-block
+        let momentary = self.mean_square(MOMENTARY_SUB_BLOCKS);
+        let short_term = self.mean_square(SHORT_TERM_SUB_BLOCKS);
+        self.loudness.momentary = to_lufs(momentary);
+        self.loudness.short_term = to_lufs(short_term);
+        if self.num_sub_blocks >= MOMENTARY_SUB_BLOCKS {
+            self.momentary_blocks.add(self.loudness.momentary);
+            if let Some(integrated) = self.momentary_blocks.gated_mean(INTEGRATED_RELATIVE_GATE) {
+                self.loudness.integrated = integrated;
+            }
+        }
+        if self.num_sub_blocks >= SHORT_TERM_SUB_BLOCKS {
+            self.short_term_blocks.add(self.loudness.short_term);
+            self.loudness.range = self.short_term_blocks.range();
+        }
+    }
+
+    /// Mean square over the last `n` sub-blocks, or fewer if there aren't as many yet
+    fn mean_square(&self, n: usize) -> f64 {
+        let n = usize::min(n, self.num_sub_blocks);
+        let sum: f64 = (1..=n)
+            .map(|i| {
+                let idx = (self.sub_block_idx + SHORT_TERM_SUB_BLOCKS - i) % SHORT_TERM_SUB_BLOCKS;
+                self.sub_blocks[idx]
+            })
+            .sum();
+        sum / n as f64
+    }
+}
+
+fn to_lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+fn to_mean_square(lufs: f64) -> f64 {
+    10f64.powf((lufs + 0.691) / 10.0)
+}
+
+fn amp_to_db(amp: f64) -> f64 {
+    20.0 * amp.log10()
+}
+
+/// Number of blocks per loudness bin, for blocks above the absolute gate
+struct Histogram {
+    counts: Vec<u64>,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            counts: vec![0; HISTOGRAM_BINS],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.counts.fill(0);
+    }
+
+    fn add(&mut self, lufs: f64) {
+        if lufs >= ABSOLUTE_GATE {
+            let bin = ((lufs - ABSOLUTE_GATE) / HISTOGRAM_STEP) as usize;
+            self.counts[usize::min(bin, HISTOGRAM_BINS - 1)] += 1;
+        }
+    }
+
+    fn bin_lufs(bin: usize) -> f64 {
+        ABSOLUTE_GATE + (bin as f64 + 0.5) * HISTOGRAM_STEP
+    }
+
+    fn bin(lufs: f64) -> usize {
+        let bin = ((lufs - ABSOLUTE_GATE) / HISTOGRAM_STEP).max(0.0) as usize;
+        usize::min(bin, HISTOGRAM_BINS)
+    }
+
+    /// Loudness of the blocks louder than `min_lufs`
+    fn mean(&self, min_lufs: f64) -> Option<f64> {
+        let mut count = 0;
+        let mut sum = 0.0;
+        for bin in Self::bin(min_lufs)..HISTOGRAM_BINS {
+            count += self.counts[bin];
+            sum += self.counts[bin] as f64 * to_mean_square(Self::bin_lufs(bin));
+        }
+        (count > 0).then(|| to_lufs(sum / count as f64))
+    }
+
+    /// Loudness of the blocks above the relative gate, which is set relative to the loudness of
+    /// all blocks above the absolute gate.
+    fn gated_mean(&self, relative_gate: f64) -> Option<f64> {
+        let ungated = self.mean(ABSOLUTE_GATE)?;
+        self.mean(ungated + relative_gate)
+    }
+
+    /// Loudness range: the difference between the 10th and 95th percentile of the blocks above
+    /// the relative gate.
+    fn range(&self) -> f64 {
+        let Some(ungated) = self.mean(ABSOLUTE_GATE) else {
+            return 0.0;
+        };
+        let start = Self::bin(ungated + RANGE_RELATIVE_GATE);
+        let total: u64 = self.counts[start..].iter().sum();
+        if total == 0 {
+            return 0.0;
+        }
+        let percentile = |p: f64| {
+            let target = (total as f64 * p).ceil().max(1.0) as u64;
+            let mut count = 0;
+            for bin in start..HISTOGRAM_BINS {
+                count += self.counts[bin];
+                if count >= target {
+                    return Self::bin_lufs(bin);
+                }
+            }
+            Self::bin_lufs(HISTOGRAM_BINS - 1)
+        };
+        percentile(0.95) - percentile(0.1)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    /// High shelf that models the acoustic effect of the head
+    fn pre_filter(sample_rate: f64) -> Self {
+        let f0 = 1681.974450955533;
+        let gain = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / sample_rate).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Self {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    /// Revised low-frequency B-curve high pass
+    fn rlb_filter(sample_rate: f64) -> Self {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Self {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.z = [0.0; 2];
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// Estimates the peak level between samples by upsampling with a windowed sinc interpolator
+struct TruePeak {
+    coefficients: [[f64; INTERPOLATION_TAPS]; OVERSAMPLING],
+    history: [[f64; INTERPOLATION_TAPS]; 2],
+    pos: usize,
+    peak: f64,
+}
+
+impl TruePeak {
+    fn new() -> Self {
+        let center = (INTERPOLATION_TAPS / 2 - 1) as f64;
+        let half_width = (INTERPOLATION_TAPS / 2) as f64;
+        let mut coefficients = [[0.0; INTERPOLATION_TAPS]; OVERSAMPLING];
+        for (phase, taps) in coefficients.iter_mut().enumerate() {
+            for (k, coefficient) in taps.iter_mut().enumerate() {
+                let t = k as f64 - center - phase as f64 / OVERSAMPLING as f64;
+                let sinc = if t == 0.0 {
+                    1.0
+                } else {
+                    (PI * t).sin() / (PI * t)
+                };
+                let window = if t.abs() < half_width {
+                    0.5 + 0.5 * (PI * t / half_width).cos()
+                } else {
+                    0.0
+                };
+                *coefficient = sinc * window;
+            }
+        }
+        Self {
+            coefficients,
+            history: [[0.0; INTERPOLATION_TAPS]; 2],
+            pos: 0,
+            peak: 0.0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.history = [[0.0; INTERPOLATION_TAPS]; 2];
+        self.peak = 0.0;
+    }
+
+    fn add_sample(&mut self, ch: usize, sample: f64) {
+        let history = &mut self.history[ch];
+        history[self.pos] = sample;
+        for taps in &self.coefficients {
+            let mut value = 0.0;
+            for (k, coefficient) in taps.iter().enumerate() {
+                value +=
+                    coefficient * history[(self.pos + INTERPOLATION_TAPS - k) % INTERPOLATION_TAPS];
+            }
+            self.peak = f64::max(self.peak, value.abs());
+        }
+        if ch == 1 {
+            self.pos = (self.pos + 1) % INTERPOLATION_TAPS;
+        }
+    }
+
+    fn value(&self) -> f64 {
+        self.peak
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 44100.0;
+
+    fn sine(seconds: f64, amplitude: f32) -> Vec<Stereo> {
+        let len = (SAMPLE_RATE * seconds) as usize;
+        (0..len)
+            .map(|i| {
+                let phase = 2.0 * PI * 997.0 * i as f64 / SAMPLE_RATE;
+                Stereo::new([amplitude * phase.sin() as f32; 2])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sine_loudness() {
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE);
+        // A sine at -20 dBFS in both channels measures -20 LUFS
+        meter.process(&sine(5.0, 0.1));
+        let loudness = meter.value();
+        assert!((loudness.momentary + 20.0).abs() < 0.1);
+        assert!((loudness.short_term + 20.0).abs() < 0.1);
+        assert!((loudness.integrated + 20.0).abs() < 0.1);
+        assert!(loudness.range < 0.5);
+        assert!((loudness.true_peak + 20.0).abs() < 0.1);
+
+        // Silence is gated, so the integrated loudness doesn't change
+        meter.process(&sine(5.0, 0.0));
+        let loudness = meter.value();
+        assert!(loudness.momentary < ABSOLUTE_GATE);
+        assert!((loudness.integrated + 20.0).abs() < 0.1);
+
+        meter.reset();
+        assert!(meter.value().integrated.is_infinite());
+    }
+
+    #[test]
+    fn loudness_range() {
+        // EBU Tech 3342 test signal: 20 seconds at -20 dBFS followed by 20 seconds at -30 dBFS
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE);
+        meter.process(&sine(20.0, 0.1));
+        meter.process(&sine(20.0, 0.0316));
+        assert!((meter.value().range - 10.0).abs() < 1.0);
+    }
+}
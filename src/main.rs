@@ -5,12 +5,18 @@ extern crate lazy_static;
 
 mod app;
 mod audio;
+mod effects;
 mod engine;
 mod env;
 mod files;
+mod history;
 mod input;
+mod loudness;
+mod mixer;
 mod params;
 mod pattern;
+mod project;
+mod render;
 mod sampler;
 mod view;
 
@@ -134,6 +140,9 @@ fn run_app(
     loop {
         let engine_state = engine_state_handle.read();
         app.engine_state.clone_from(engine_state);
+        if let Some(message) = app.finished_render() {
+            view.message = Some(message);
+        }
         terminal.draw(|f| view::render(&app, &mut view, f))?;
 
         match input.recv()? {
@@ -141,9 +150,19 @@ fn run_app(
                 Event::Key(event) if event.kind == KeyEventKind::Press => {
                     let msg = input::handle_key_event(&app, &mut view, event);
                     if msg.is_exit() {
+                        if app.is_rendering() {
+                            view.message = Some(String::from("waiting for render to finish"));
+                            terminal.draw(|f| view::render(&app, &mut view, f))?;
+                            app.wait_for_renders();
+                        }
                         return Ok(());
                     }
-                    app.send(msg)?;
+                    let is_load = matches!(msg, Msg::LoadProject(_));
+                    match app.send(msg) {
+                        Ok(()) if is_load => view.reset_selections(),
+                        Ok(()) => {}
+                        Err(err) => view.message = Some(format!("error: {}", err)),
+                    }
                 }
                 _ => {}
             },
@@ -0,0 +1,132 @@
+use crate::app::{TrackId, TrackRoute};
+use crate::engine::TOTAL_TRACKS;
+
+/// Mixer state shared by all tracks. When any track is soloed, tracks that don't contribute to
+/// or receive audio from a soloed track are silenced, unless they're marked as solo-safe.
+pub struct Mixer {
+    /// One entry per track in processing order
+    silenced: Vec<bool>,
+    /// Track is soloed, or its output ends up in a soloed track
+    feeds_solo: Vec<bool>,
+    /// Track is soloed, or receives the output of a soloed track
+    fed_by_solo: Vec<bool>,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self {
+            silenced: Vec::with_capacity(TOTAL_TRACKS),
+            feeds_solo: Vec::with_capacity(TOTAL_TRACKS),
+            fed_by_solo: Vec::with_capacity(TOTAL_TRACKS),
+        }
+    }
+
+    /// Recompute the solo state. `routes` is in processing order, so tracks always come before
+    /// the buses they're routed to.
+    pub fn update(&mut self, routes: &[TrackRoute]) {
+        self.silenced.clear();
+        self.feeds_solo.clear();
+        self.fed_by_solo.clear();
+        if !routes.iter().any(|route| route.solo) {
+            self.silenced.resize(routes.len(), false);
+            return;
+        }
+
+        self.feeds_solo.resize(routes.len(), false);
+        for (idx, route) in routes.iter().enumerate().rev() {
+            self.feeds_solo[idx] = route.solo
+                || destinations(route)
+                    .any(|id| position(routes, id).is_some_and(|dest| self.feeds_solo[dest]));
+        }
+
+        self.fed_by_solo.resize(routes.len(), false);
+        for (idx, route) in routes.iter().enumerate() {
+            if self.fed_by_solo[idx] || route.solo {
+                self.fed_by_solo[idx] = true;
+                for dest in destinations(route).filter_map(|id| position(routes, id)) {
+                    self.fed_by_solo[dest] = true;
+                }
+            }
+        }
+
+        for (idx, route) in routes.iter().enumerate() {
+            let audible = route.solo_safe || self.feeds_solo[idx] || self.fed_by_solo[idx];
+            self.silenced.push(!audible);
+        }
+    }
+
+    /// Returns true if the track at `idx` in the processing order is silenced by solo
+    pub fn is_silenced(&self, idx: usize) -> bool {
+        self.silenced.get(idx).copied().unwrap_or(false)
+    }
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn destinations(route: &TrackRoute) -> impl Iterator<Item = TrackId> + '_ {
+    route
+        .output
+        .iter()
+        .chain(route.sends.iter().flatten())
+        .copied()
+}
+
+fn position(routes: &[TrackRoute], id: TrackId) -> Option<usize> {
+    routes.iter().position(|route| route.id == id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::NUM_SENDS;
+
+    fn route(output: Option<TrackId>) -> TrackRoute {
+        TrackRoute {
+            id: TrackId::new(),
+            output,
+            sends: [None; NUM_SENDS],
+            solo: false,
+            solo_safe: false,
+        }
+    }
+
+    fn silenced(routes: &[TrackRoute]) -> Vec<bool> {
+        let mut mixer = Mixer::new();
+        mixer.update(routes);
+        (0..routes.len())
+            .map(|idx| mixer.is_silenced(idx))
+            .collect()
+    }
+
+    #[test]
+    fn solo() {
+        let group = route(None);
+        let reverb = route(Some(group.id));
+        let kick = route(Some(group.id));
+        let mut hats = route(Some(group.id));
+        hats.sends[0] = Some(reverb.id);
+        let bass = route(None);
+
+        let mut routes = vec![kick, hats, bass, reverb, group];
+        assert_eq!(vec![false; 5], silenced(&routes));
+
+        // Soloing a track keeps the buses it's routed and sent to
+        routes[1].solo = true;
+        assert_eq!(vec![true, false, true, false, false], silenced(&routes));
+
+        // Soloing a bus keeps the tracks routed to it
+        routes[1].solo = false;
+        routes[3].solo = true;
+        assert_eq!(vec![true, false, true, false, false], silenced(&routes));
+
+        // Solo-safe buses are never silenced
+        routes[3].solo = false;
+        routes[3].solo_safe = true;
+        routes[0].solo = true;
+        assert_eq!(vec![false, true, true, false, false], silenced(&routes));
+    }
+}
@@ -1,9 +1,11 @@
 use ratatui::style::Color;
 
-use crate::{app::random_color, engine::INSTRUMENT_TRACKS, engine::TICKS_PER_LINE};
-use std::ops::{Add, Sub};
+use crate::{app::random_color, engine::MAX_INSTRUMENTS, engine::TICKS_PER_LINE};
+use std::ops::RangeInclusive;
 
-pub const INPUTS_PER_STEP: usize = 6;
+pub const MAX_NOTE_COLUMNS: usize = 4;
+/// Number of cells stored per step, see `Step` for the layout
+pub const STEP_CELLS: usize = VOLUME + 1 + (MAX_NOTE_COLUMNS - 1) * NOTE_INPUTS;
 pub const MAX_PITCH: u8 = 109;
 pub const NOTE_OFF: u8 = MAX_PITCH;
 pub const MAX_PATTERNS: usize = 256;
@@ -16,74 +18,52 @@ const MAX_VELOCITY: u8 = 127;
 const FX_CHORD: char = 'C';
 const FX_OFFSET: char = 'O';
 const FX_VELOCITY: char = 'V';
-
+const FX_RETRIGGER: char = 'R';
+const FX_CUT: char = 'K';
+const FX_SLIDE_UP: char = 'U';
+const FX_SLIDE_DOWN: char = 'D';
+const FX_GLIDE: char = 'G';
+const FX_ARPEGGIO: char = 'A';
+const FX_PROBABILITY: char = 'P';
+const FX_CONDITION: char = 'T';
+
+// Inputs of a single note column (pitch, instrument and volume) and of the two effect columns
+// shared by all note columns of a track
+pub const NOTE_INPUTS: usize = 3;
+const EFFECT_INPUTS: usize = 4;
+const NOTE_VOLUME: usize = 2;
+
+// Cell indices of the first note column and the effects. Volume was added later, so it comes
+// after the effects, followed by the cells of the other note columns.
 const PITCH: usize = 0;
 const INSTR: usize = 1;
 const FX_CMD1: usize = 2;
 const FX_VAL1: usize = 3;
 const FX_CMD2: usize = 4;
 const FX_VAL2: usize = 5;
+const VOLUME: usize = 6;
 
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct Position {
     pub line: usize,
+    pub track: usize,
+    /// Input within the track, note columns come first followed by the effect columns
     pub column: usize,
 }
 
-impl Add for Position {
-    type Output = Position;
-    fn add(self, rhs: Self) -> Self::Output {
-        Self::new(self.line + rhs.line, self.column + rhs.column)
-    }
-}
-
-impl Sub for Position {
-    type Output = Position;
-    fn sub(self, rhs: Self) -> Self::Output {
-        Self::new(self.line - rhs.line, self.column - rhs.column)
-    }
-}
-
 impl Position {
-    fn new(line: usize, column: usize) -> Self {
-        Self { line, column }
-    }
-
-    pub fn track(&self) -> usize {
-        self.column / INPUTS_PER_STEP
-    }
-
-    fn input(&self) -> Input {
-        let i = self.column % INPUTS_PER_STEP;
-        use InputKind::*;
-        match i {
-            PITCH => Input::new(i, Pitch),
-            INSTR => Input::new(i, Instr),
-            FX_CMD1 | FX_CMD2 => Input::new(i, EffectCmd),
-            FX_VAL1 | FX_VAL2 => Input::new(i, EffectVal),
-            _ => unreachable!(),
+    pub fn new(line: usize, track: usize, column: usize) -> Self {
+        Self {
+            line,
+            track,
+            column,
         }
     }
-
-    pub fn is_pitch_input(&self) -> bool {
-        self.column % INPUTS_PER_STEP == PITCH
-    }
-}
-
-#[derive(Default)]
-pub struct Rect {
-    pub lines: usize,
-    pub columns: usize,
-}
-
-impl Rect {
-    fn new(lines: usize, columns: usize) -> Self {
-        Self { lines, columns }
-    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Pattern {
+    pub name: Option<String>,
     pub color: Color,
     pub tracks: Vec<Track>,
 }
@@ -92,18 +72,27 @@ impl Pattern {
     pub fn new(num_tracks: usize) -> Self {
         let mut tracks = Vec::with_capacity(num_tracks);
         for _ in 0..num_tracks {
-            tracks.push(Track {
-                steps: vec![Step::default(); DEFAULT_PATTERN_LEN],
-            })
+            tracks.push(Track::new(DEFAULT_PATTERN_LEN))
         }
         Self {
+            name: None,
             color: random_color(),
             tracks,
         }
     }
 
-    pub fn size(&self) -> Rect {
-        Rect::new(self.len(), self.tracks.len() * INPUTS_PER_STEP)
+    pub fn with_steps(color: Color, tracks: Vec<Vec<Step>>) -> Self {
+        Self {
+            name: None,
+            color,
+            tracks: tracks
+                .into_iter()
+                .map(|steps| Track {
+                    steps,
+                    note_columns: 1,
+                })
+                .collect(),
+        }
     }
 
     pub fn len(&self) -> usize {
@@ -124,115 +113,306 @@ impl Pattern {
         }
     }
 
+    /// Insert an empty track before the track at `idx`
+    pub fn insert_track(&mut self, idx: usize) {
+        self.tracks.insert(idx, Track::new(self.len()));
+    }
+
+    pub fn remove_track(&mut self, idx: usize) {
+        // Patterns always have at least one track, which is used for the pattern length
+        if self.tracks.len() > 1 {
+            self.tracks.remove(idx);
+        }
+    }
+
+    pub fn note_columns(&self, track_idx: usize) -> usize {
+        self.tracks[track_idx].note_columns
+    }
+
+    /// Change the number of note columns of a track. Notes in columns that are removed are
+    /// cleared.
+    pub fn set_note_columns(&mut self, track_idx: usize, note_columns: usize) {
+        let track = &mut self.tracks[track_idx];
+        track.note_columns = note_columns.clamp(1, MAX_NOTE_COLUMNS);
+        for step in &mut track.steps {
+            for column in track.note_columns..MAX_NOTE_COLUMNS {
+                for input in 0..NOTE_INPUTS {
+                    step.cells[note_cell(column, input)] = None;
+                }
+            }
+        }
+    }
+
+    /// Use the same number of note columns per track as another pattern
+    pub fn copy_layout(&mut self, other: &Pattern) {
+        for (idx, track) in other.tracks.iter().enumerate().take(self.tracks.len()) {
+            self.set_note_columns(idx, track.note_columns);
+        }
+    }
+
+    /// Whether both patterns have the same number of note columns per track
+    pub fn has_layout_of(&self, other: &Pattern) -> bool {
+        self.tracks.len() == other.tracks.len()
+            && self
+                .tracks
+                .iter()
+                .zip(&other.tracks)
+                .all(|(a, b)| a.note_columns == b.note_columns)
+    }
+
+    /// Number of inputs of a track
+    pub fn inputs(&self, track_idx: usize) -> usize {
+        self.tracks[track_idx].inputs()
+    }
+
+    /// Move a position back into the pattern, e.g. after the pattern got smaller
+    pub fn clamp(&self, pos: Position) -> Position {
+        let line = usize::min(self.len() - 1, pos.line);
+        let track = usize::min(self.tracks.len() - 1, pos.track);
+        let column = usize::min(self.inputs(track) - 1, pos.column);
+        Position::new(line, track, column)
+    }
+
+    pub fn is_pitch_input(&self, pos: Position) -> bool {
+        matches!(self.input(pos).kind, InputKind::Pitch)
+    }
+
+    /// Change the instrument of every step that has one set, e.g. after the instrument list
+    /// was edited. Returning `None` clears the instrument.
+    pub fn map_instruments(&mut self, f: impl Fn(u8) -> Option<u8>) {
+        for step in self.tracks.iter_mut().flat_map(|track| &mut track.steps) {
+            for column in 0..MAX_NOTE_COLUMNS {
+                let instr = step.cell_mut(note_cell(column, INSTR));
+                *instr = instr.and_then(&f);
+            }
+        }
+    }
+
     pub fn steps(&self, track_idx: usize) -> &Vec<Step> {
         &self.tracks[track_idx].steps
     }
 
     pub fn incr(&mut self, pos: Position, step_size: StepSize) {
+        let input = self.input(pos);
         let step = self.step_mut(pos);
-        step.incr(pos.input(), step_size);
+        step.incr(input, step_size);
     }
 
     pub fn decr(&mut self, pos: Position, step_size: StepSize) {
+        let input = self.input(pos);
         let step = self.step_mut(pos);
-        step.decr(pos.input(), step_size);
+        step.decr(input, step_size);
     }
 
     pub fn set_key(&mut self, pos: Position, octave: u8, key: char) {
+        let input = self.input(pos);
         let step = self.step_mut(pos);
-        step.set_key(pos.input(), octave, key);
+        step.set_key(input, octave, key);
     }
 
     pub fn clear(&mut self, pos: Position) {
+        let input = self.input(pos);
         let step = self.step_mut(pos);
-        step.clear(pos.input())
+        step.clear(input)
+    }
+
+    fn input(&self, pos: Position) -> Input {
+        self.tracks[pos.track].input(pos.column)
     }
 
     fn step_mut(&mut self, pos: Position) -> &mut Step {
-        &mut self.tracks[pos.track()].steps[pos.line]
+        &mut self.tracks[pos.track].steps[pos.line]
     }
 
     fn step(&self, pos: Position) -> &Step {
-        &self.tracks[pos.track()].steps[pos.line]
+        &self.tracks[pos.track].steps[pos.line]
     }
 
     fn cell(&self, pos: Position) -> Option<u8> {
-        *self.step(pos).cell(pos.input().idx)
+        *self.step(pos).cell(self.input(pos).idx)
     }
 
     fn cell_mut(&mut self, pos: Position) -> &mut Option<u8> {
-        self.step_mut(pos).cell_mut(pos.input().idx)
+        let idx = self.input(pos).idx;
+        self.step_mut(pos).cell_mut(idx)
     }
 
-    // For each track in the pattern, return notes that should be played on the given tick. The
-    // tick is relative to the start of the pattern.
+    // For each track in the pattern, return notes that should be played or released on the given
+    // tick. The tick is relative to the start of the pattern. Each note column of a track produces
+    // its own notes, the effects of the step apply to all of them.
     pub fn events(&self, tick: usize) -> impl Iterator<Item = NoteEvent> + '_ {
         let line = tick / TICKS_PER_LINE;
         self.tracks.iter().enumerate().flat_map(move |(i, track)| {
             let step = &track.steps[line];
-            let line_tick = line * TICKS_PER_LINE;
-
-            let mut has_offset = false;
-            let offset_match = step.offsets().any(|offset| {
-                has_offset = true;
-                line_tick + offset as usize == tick
-            });
-
-            // TODO: ensure that instrument is always set when pitch is set (it will use the
-            // instrument you have selected in the instrument list). Otherwise the behavior
-            // here becomes inconsistent when editing the instrument list.
-            let instrument = step.instrument().unwrap_or(i as u8) as usize;
-            let velocity = step.velocity();
-
-            let notes = if (!has_offset && line_tick == tick) || offset_match {
-                let iter = step.notes().map(move |pitch| {
-                    let note = if pitch == NOTE_OFF {
-                        Note::Off
-                    } else {
-                        Note::On(pitch, velocity)
-                    };
-                    NoteEvent {
-                        note,
-                        track: i,
-                        instrument,
-                    }
-                });
-                Some(iter)
-            } else {
-                None
-            };
-            notes.into_iter().flatten()
+            let line_tick = tick % TICKS_PER_LINE;
+            let trigger = step.trigger_at(line_tick);
+            let is_cut = step.is_cut_at(line_tick);
+            let slide = step.slide_at(line_tick);
+
+            (0..track.note_columns).flat_map(move |column| {
+                // TODO: ensure that instrument is always set when pitch is set (it will use the
+                // instrument you have selected in the instrument list). Otherwise the behavior
+                // here becomes inconsistent when editing the instrument list.
+                let instrument = step.instrument(column).unwrap_or(i as u8) as usize;
+                let velocity = step.retrigger_velocity(column, trigger.unwrap_or(0));
+                let event = move |note| NoteEvent {
+                    note,
+                    track: i,
+                    column,
+                    instrument,
+                };
+
+                // A cut releases whatever is playing in the column, even without a note on the
+                // step itself
+                let cut = is_cut.then(|| event(Note::Off));
+                // Gliding notes bend the playing note instead of triggering a new one
+                let bend = step.glide_at(column, line_tick).or(slide).map(event);
+                let is_stacked = !step.is_glide(column) && !step.is_arpeggio(column);
+                let notes = step
+                    .notes(column)
+                    .filter(move |_| trigger.is_some() && is_stacked)
+                    .chain(step.arpeggio_at(column, line_tick))
+                    .map(move |pitch| {
+                        if pitch == NOTE_OFF {
+                            event(Note::Off)
+                        } else {
+                            event(Note::On(pitch, velocity))
+                        }
+                    });
+                cut.into_iter().chain(notes).chain(bend)
+            })
         })
     }
 
     pub fn copy(&mut self, start: Position, src: &Pattern, selection: &Selection) {
-        let dst_size = self.size();
-        let src_size = selection.size();
-        if dst_size.lines - start.line < src_size.lines
-            || dst_size.columns - start.column < src_size.columns
-        {
+        let src_start = selection.start();
+        let src_end = selection.end();
+        let lines = src_end.line - src_start.line + 1;
+        let last_track = start.track + src_end.track - src_start.track;
+        if self.len() - start.line < lines || last_track >= self.tracks.len() {
             // TODO: truncate selection or automatically increase dst pattern size?
             return;
         }
 
-        let src_start = selection.start();
-
         // Check that src and dst are aligned.
-        // TODO: allow pasting from fx1 into fx2 and vice versa
         // TODO: return error if selection can't be copied
-        if src_start.input().idx != start.input().idx {
+        if src.input(src_start).kind != self.input(start).kind {
             return;
         }
 
-        for pos in selection.iter() {
-            *self.cell_mut(start + pos) = src.cell(src_start + pos);
+        for track in src_start.track..=src_end.track {
+            let src_track = &src.tracks[track];
+            let dst_track = start.track + track - src_start.track;
+            let (first, offset) = if track == src_start.track {
+                (src_start.column, Some((src_start.column, start.column)))
+            } else {
+                (0, None)
+            };
+            let last = if track == src_end.track {
+                src_end.column
+            } else {
+                src_track.inputs() - 1
+            };
+            for column in first..=last {
+                let Some(dst_column) =
+                    self.tracks[dst_track].aligned_column(src_track, column, offset)
+                else {
+                    continue;
+                };
+                for line in 0..lines {
+                    let src_pos = Position::new(src_start.line + line, track, column);
+                    let dst_pos = Position::new(start.line + line, dst_track, dst_column);
+                    *self.cell_mut(dst_pos) = src.cell(src_pos);
+                }
+            }
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Track {
     steps: Vec<Step>,
+    note_columns: usize,
+}
+
+impl Track {
+    fn new(len: usize) -> Self {
+        Self {
+            steps: vec![Step::default(); len],
+            note_columns: 1,
+        }
+    }
+
+    fn inputs(&self) -> usize {
+        self.note_columns * NOTE_INPUTS + EFFECT_INPUTS
+    }
+
+    /// The column that lines up with a column of another track, which can have a different number
+    /// of note columns. Note inputs go into the same note column and effect inputs into the same
+    /// effect column, shifted by the distance between the `(src, dst)` start columns of a paste.
+    fn aligned_column(
+        &self,
+        src: &Track,
+        column: usize,
+        offset: Option<(usize, usize)>,
+    ) -> Option<usize> {
+        let (src_start, dst_start) = offset.unwrap_or((0, 0));
+        let src_notes = src.note_columns * NOTE_INPUTS;
+        let dst_notes = self.note_columns * NOTE_INPUTS;
+        let dst_column = if column >= src_notes && src_start < src_notes {
+            dst_notes + column - src_notes
+        } else {
+            dst_start + column - src_start
+        };
+        let fits = if column < src_notes {
+            dst_column < dst_notes
+        } else {
+            dst_column < self.inputs()
+        };
+        fits.then_some(dst_column)
+    }
+
+    fn input(&self, column: usize) -> Input {
+        use InputKind::*;
+        let note_inputs = self.note_columns * NOTE_INPUTS;
+        if column < note_inputs {
+            let input = column % NOTE_INPUTS;
+            let idx = note_cell(column / NOTE_INPUTS, input);
+            match input {
+                PITCH => Input::new(idx, Pitch),
+                INSTR => Input::new(idx, Instr),
+                _ => Input::new(idx, Volume),
+            }
+        } else {
+            let idx = FX_CMD1 + column - note_inputs;
+            match idx {
+                FX_CMD1 | FX_CMD2 => Input::new(idx, EffectCmd),
+                FX_VAL1 | FX_VAL2 => Input::new(idx, EffectVal),
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+/// Index of a cell of a note column, `input` is one of `PITCH`, `INSTR` or `NOTE_VOLUME`
+fn is_valid_value(kind: InputKind, val: u8) -> bool {
+    use InputKind::*;
+    match kind {
+        Pitch => val <= MAX_PITCH,
+        Instr => (val as usize) < MAX_INSTRUMENTS,
+        Volume => val <= MAX_VELOCITY,
+        EffectCmd => (val as char).is_ascii_alphabetic(),
+        EffectVal => true,
+    }
+}
+
+fn note_cell(column: usize, input: usize) -> usize {
+    match (column, input) {
+        (0, PITCH) => PITCH,
+        (0, INSTR) => INSTR,
+        (0, _) => VOLUME,
+        _ => VOLUME + 1 + (column - 1) * NOTE_INPUTS + input,
+    }
 }
 
 #[derive(Clone)]
@@ -240,12 +420,44 @@ pub struct NoteEvent {
     pub note: Note,
     pub instrument: usize,
     pub track: usize,
+    /// Note column of the track that the event comes from
+    pub column: usize,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Note {
     On(u8, u8),
     Off,
+    /// Bends the pitch of playing notes by the given number of cents
+    Slide(i16),
+    /// Bends the pitch of playing notes towards a note, reaching it after the given number of
+    /// ticks
+    Glide(u8, u8),
+}
+
+/// Condition for playing a step, depending on how often the pattern has been played and on the
+/// fill mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    First,
+    NotFirst,
+    Fill,
+    NotFill,
+    /// Play on the given pass out of every n passes, counting from 1
+    Every(u8, u8),
+}
+
+impl Condition {
+    /// `pass` is the number of times the pattern has been played before, starting at 0
+    pub fn is_met(&self, pass: u32, fill: bool) -> bool {
+        match *self {
+            Condition::First => pass == 0,
+            Condition::NotFirst => pass > 0,
+            Condition::Fill => fill,
+            Condition::NotFill => !fill,
+            Condition::Every(n, every) => pass % every as u32 == (n - 1) as u32,
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -260,17 +472,21 @@ impl Input {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 enum InputKind {
     Pitch,
     Instr,
+    Volume,
     EffectCmd,
     EffectVal,
 }
 
-#[derive(Clone, Debug, Default)]
+/// The cells of a step are stored in the order pitch, instrument, fx1 command, fx1 value, fx2
+/// command, fx2 value and volume, followed by pitch, instrument and volume for each of the other
+/// note columns.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Step {
-    cells: [Option<u8>; INPUTS_PER_STEP],
+    cells: [Option<u8>; STEP_CELLS],
 }
 
 impl Step {
@@ -278,7 +494,7 @@ impl Step {
         use InputKind::*;
         let val = match input.kind {
             Pitch => key_to_pitch(octave, key),
-            Instr | EffectVal => match (self.cell(input.idx), key.to_digit(10)) {
+            Instr | Volume | EffectVal => match (self.cell(input.idx), key.to_digit(10)) {
                 (Some(val), Some(d)) => {
                     let d = d as i16;
                     let val = *val as i16 * 10 + d;
@@ -318,26 +534,30 @@ impl Step {
     }
 
     fn set(&mut self, input: Input, val: u8) {
-        use InputKind::*;
-        match input.kind {
-            Pitch => {
-                if val > MAX_PITCH {
-                    return;
-                }
-            }
-            Instr => {
-                if val as usize >= INSTRUMENT_TRACKS {
-                    return;
-                }
-            }
-            EffectCmd => {
-                if !(val as char).is_ascii_alphabetic() {
-                    return;
-                }
-            }
-            EffectVal => {}
+        if is_valid_value(input.kind, val) {
+            *self.cell_mut(input.idx) = Some(val);
         }
-        *self.cell_mut(input.idx) = Some(val);
+    }
+
+    /// Whether all cells hold values that could have been entered in the editor
+    pub fn is_valid(&self) -> bool {
+        use InputKind::*;
+        let notes = (0..MAX_NOTE_COLUMNS).flat_map(|column| {
+            [
+                (note_cell(column, PITCH), Pitch),
+                (note_cell(column, INSTR), Instr),
+                (note_cell(column, NOTE_VOLUME), Volume),
+            ]
+        });
+        let effects = [
+            (FX_CMD1, EffectCmd),
+            (FX_VAL1, EffectVal),
+            (FX_CMD2, EffectCmd),
+            (FX_VAL2, EffectVal),
+        ];
+        notes
+            .chain(effects)
+            .all(|(idx, kind)| self.cell(idx).is_none_or(|val| is_valid_value(kind, val)))
     }
 
     fn cell_mut(&mut self, idx: usize) -> &mut Option<u8> {
@@ -348,12 +568,24 @@ impl Step {
         &self.cells[idx]
     }
 
-    pub fn pitch(&self) -> Option<u8> {
-        *self.cell(PITCH)
+    pub fn cells(&self) -> &[Option<u8>; STEP_CELLS] {
+        &self.cells
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(|cell| cell.is_none())
+    }
+
+    pub fn pitch(&self, column: usize) -> Option<u8> {
+        *self.cell(note_cell(column, PITCH))
+    }
+
+    pub fn instrument(&self, column: usize) -> Option<u8> {
+        *self.cell(note_cell(column, INSTR))
     }
 
-    pub fn instrument(&self) -> Option<u8> {
-        *self.cell(INSTR)
+    pub fn volume(&self, column: usize) -> Option<u8> {
+        *self.cell(note_cell(column, NOTE_VOLUME))
     }
 
     pub fn effect_cmd(&self, idx: usize) -> Option<u8> {
@@ -366,30 +598,216 @@ impl Step {
         *self.cell(FX_VAL1 + idx * 2)
     }
 
-    fn notes(&self) -> impl Iterator<Item = u8> {
+    fn notes(&self, column: usize) -> impl Iterator<Item = u8> {
+        let pitch = self.pitch(column);
         let chord = if let Some(chord) = self.chord() {
-            let iter = ChordIter {
-                root: self.pitch(),
-                chord,
-            };
+            let iter = ChordIter { root: pitch, chord };
             Some(iter)
         } else {
             None
         };
-        self.pitch().into_iter().chain(chord.into_iter().flatten())
+        pitch.into_iter().chain(chord.into_iter().flatten())
+    }
+
+    /// Chance in percent that the step is played, set with `P x`
+    pub fn probability(&self) -> Option<u8> {
+        self.effects()
+            .find(|e| e.cmd == FX_PROBABILITY)
+            .map(|e| u8::min(e.value, 100))
+    }
+
+    /// Condition set with `T x`. 1 plays the step on the first pass of the pattern only, 2 on all
+    /// passes except the first, 3 in fill mode and 4 when not in fill mode. Two digits `T ab` play
+    /// the step on pass a out of every b passes, e.g. `T 24` on the second of every four.
+    pub fn condition(&self) -> Option<Condition> {
+        let value = self.effects().find(|e| e.cmd == FX_CONDITION)?.value;
+        match (value, value / 10, value % 10) {
+            (1, ..) => Some(Condition::First),
+            (2, ..) => Some(Condition::NotFirst),
+            (3, ..) => Some(Condition::Fill),
+            (4, ..) => Some(Condition::NotFill),
+            (10..=99, n, every) if (1..=every).contains(&n) => Some(Condition::Every(n, every)),
+            _ => None,
+        }
+    }
+
+    fn is_arpeggio(&self, column: usize) -> bool {
+        self.effects().any(|e| e.cmd == FX_ARPEGGIO)
+            && self.pitch(column).is_some_and(|pitch| pitch != NOTE_OFF)
+    }
+
+    /// `A xy` plays the root note and the notes x and y semitones above it one after another,
+    /// switching every 4 ticks. A second `A` in the other effect column sets the number of ticks
+    /// per note instead. Combined with a chord, the notes of the chord are played one after
+    /// another instead and the value sets the number of ticks per note. For both, 0 ticks spreads
+    /// the notes over the line.
+    fn arpeggio_at(&self, column: usize, tick: usize) -> Option<u8> {
+        if !self.is_arpeggio(column) || self.is_glide(column) {
+            return None;
+        }
+        let mut values = self
+            .effects()
+            .filter(|e| e.cmd == FX_ARPEGGIO)
+            .map(|e| e.value);
+        let value = values.next()?;
+        let root = self.pitch(column)?;
+
+        let mut notes = [root; 4];
+        let (len, ticks) = match self.chord() {
+            Some(chord) => {
+                let mut len = 1;
+                for pitch in (ChordIter {
+                    root: Some(root),
+                    chord,
+                }) {
+                    notes[len] = pitch;
+                    len += 1;
+                }
+                // The chord starts with the last digit, play the notes in the order they're written
+                notes[1..len].reverse();
+                (len, value)
+            }
+            None => {
+                notes[1] = root + value / 10;
+                notes[2] = root + value % 10;
+                (3, values.next().unwrap_or((TICKS_PER_LINE / 3) as u8))
+            }
+        };
+        let ticks = match ticks {
+            0 => TICKS_PER_LINE / len,
+            ticks => ticks as usize,
+        };
+        // Notes above the highest pitch would be note offs
+        let notes = notes.map(|pitch| u8::min(pitch, MAX_PITCH - 1));
+
+        let elapsed = tick.checked_sub(self.first_offset())?;
+        (elapsed % ticks == 0).then_some(notes[elapsed / ticks % len])
     }
 
     fn chord(&self) -> Option<u8> {
         self.effects().find(|e| e.cmd == FX_CHORD).map(|e| e.value)
     }
 
-    fn velocity(&self) -> u8 {
-        self.effects()
-            .find(|e| e.cmd == FX_VELOCITY)
-            .map(|e| u8::min(MAX_VELOCITY, e.value))
+    /// The volume of the note column takes precedence over the velocity effect
+    fn velocity(&self, column: usize) -> u8 {
+        self.volume(column)
+            .or_else(|| {
+                self.effects()
+                    .find(|e| e.cmd == FX_VELOCITY)
+                    .map(|e| u8::min(MAX_VELOCITY, e.value))
+            })
             .unwrap_or(DEFAULT_VELOCITY)
     }
 
+    /// Number of times the note is played within the line and the velocity ramp. `R x` plays the
+    /// note x times, `R xy` also ramps the velocity of the last note to y/5 of the step velocity.
+    /// Three digits are needed for more than 9 notes, e.g. `R120`.
+    fn retrigger(&self) -> Option<(usize, usize)> {
+        let (count, ramp) =
+            self.effects()
+                .find(|e| e.cmd == FX_RETRIGGER)
+                .map(|e| match e.value {
+                    value @ 0..=9 => (value as usize, 0),
+                    value => (value as usize / 10, value as usize % 10),
+                })?;
+        // Each note gets at least one tick
+        let count = usize::min(count, TICKS_PER_LINE - self.first_offset());
+        (count > 1).then_some((count, ramp))
+    }
+
+    fn first_offset(&self) -> usize {
+        // A retrigger can't be combined with more than one offset, as there are only two effect
+        // columns
+        self.offsets().next().unwrap_or(0) as usize
+    }
+
+    /// Returns the index of the trigger on the given tick within the line, if the notes of the
+    /// step are played on that tick
+    fn trigger_at(&self, tick: usize) -> Option<usize> {
+        match self.retrigger() {
+            Some((count, _)) => {
+                let start = self.first_offset();
+                (0..count).find(|i| start + i * (TICKS_PER_LINE - start) / count == tick)
+            }
+            _ => {
+                let mut offsets = self.offsets().peekable();
+                if offsets.peek().is_none() {
+                    (tick == 0).then_some(0)
+                } else {
+                    offsets.any(|offset| offset as usize == tick).then_some(0)
+                }
+            }
+        }
+    }
+
+    /// Whether notes are cut on the given tick within the line. `K x` releases notes x ticks after
+    /// they were triggered, unless they are retriggered before that.
+    fn is_cut_at(&self, tick: usize) -> bool {
+        let Some(ticks) = self
+            .effects()
+            .find(|e| e.cmd == FX_CUT)
+            .map(|e| e.value as usize)
+        else {
+            return false;
+        };
+        ticks > 0
+            && tick >= ticks
+            && self.trigger_at(tick - ticks).is_some()
+            && (tick + 1 - ticks..=tick).all(|t| self.trigger_at(t).is_none())
+    }
+
+    /// `U x` and `D x` slide playing notes up or down by x semitones over the course of the line
+    fn slide_at(&self, tick: usize) -> Option<Note> {
+        let cents = self
+            .effects()
+            .find_map(|e| match e.cmd {
+                FX_SLIDE_UP => Some(e.value as i32 * 100),
+                FX_SLIDE_DOWN => Some(-(e.value as i32) * 100),
+                _ => None,
+            })
+            .filter(|cents| *cents != 0)?;
+        // Spread the slide over all ticks so that it adds up to exactly x semitones
+        let (tick, ticks) = (tick as i32, TICKS_PER_LINE as i32);
+        let cents = cents * (tick + 1) / ticks - cents * tick / ticks;
+        Some(Note::Slide(cents as i16))
+    }
+
+    fn is_glide(&self, column: usize) -> bool {
+        self.effects().any(|e| e.cmd == FX_GLIDE)
+            && self.pitch(column).is_some_and(|pitch| pitch != NOTE_OFF)
+    }
+
+    /// `G x` glides playing notes to the pitch of the note column within x ticks, or by the end of
+    /// the line if x is 0
+    fn glide_at(&self, column: usize, tick: usize) -> Option<Note> {
+        if !self.is_glide(column) {
+            return None;
+        }
+        let ticks = self.effects().find(|e| e.cmd == FX_GLIDE)?.value as usize;
+        let start = self.first_offset();
+        let end = match ticks {
+            0 => TICKS_PER_LINE,
+            ticks => usize::min(TICKS_PER_LINE, start + ticks),
+        };
+        let pitch = self.pitch(column)?;
+        (start..end)
+            .contains(&tick)
+            .then(|| Note::Glide(pitch, (end - tick) as u8))
+    }
+
+    fn retrigger_velocity(&self, column: usize, trigger: usize) -> u8 {
+        let velocity = self.velocity(column) as isize;
+        match self.retrigger() {
+            Some((count, ramp)) if ramp > 0 => {
+                let target = velocity * ramp as isize / 5;
+                let velocity =
+                    velocity + (target - velocity) * trigger as isize / (count as isize - 1);
+                velocity.clamp(0, MAX_VELOCITY as isize) as u8
+            }
+            _ => velocity as u8,
+        }
+    }
+
     fn offsets(&self) -> impl Iterator<Item = u8> + '_ {
         self.effects().flat_map(|e| {
             if e.cmd == FX_OFFSET {
@@ -411,6 +829,12 @@ impl Step {
     }
 }
 
+impl From<[Option<u8>; STEP_CELLS]> for Step {
+    fn from(cells: [Option<u8>; STEP_CELLS]) -> Self {
+        Self { cells }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum StepSize {
     Default = 0,
@@ -474,6 +898,8 @@ fn key_to_pitch(octave: u8, key: char) -> Option<u8> {
     Some(pitch)
 }
 
+/// A rectangular selection of lines and inputs. Inputs are ordered by track first, so a
+/// selection can span several tracks.
 #[derive(Clone)]
 pub struct Selection {
     // The cursor position when the selection was started.
@@ -492,59 +918,36 @@ impl Selection {
         self.end = pos;
     }
 
-    pub fn contains(&self, line: usize, column: usize) -> bool {
+    pub fn contains(&self, pos: Position) -> bool {
         let start = self.start();
         let end = self.end();
-        start.line <= line && line <= end.line && start.column <= column && column <= end.column
+        start.line <= pos.line
+            && pos.line <= end.line
+            && (start.track, start.column) <= (pos.track, pos.column)
+            && (pos.track, pos.column) <= (end.track, end.column)
+    }
+
+    /// Returns the range of tracks covered by the selection
+    pub fn tracks(&self) -> RangeInclusive<usize> {
+        self.start().track..=self.end().track
     }
 
     fn start(&self) -> Position {
         let line = usize::min(self.start.line, self.end.line);
-        let col = usize::min(self.start.column, self.end.column);
-        Position::new(line, col)
+        let (track, column) = std::cmp::min(
+            (self.start.track, self.start.column),
+            (self.end.track, self.end.column),
+        );
+        Position::new(line, track, column)
     }
 
     fn end(&self) -> Position {
         let line = usize::max(self.start.line, self.end.line);
-        let col = usize::max(self.start.column, self.end.column);
-        Position::new(line, col)
-    }
-
-    fn size(&self) -> Rect {
-        let start = self.start();
-        let end = self.end();
-        Rect::new(end.line - start.line + 1, end.column - start.column + 1)
-    }
-
-    fn iter(&self) -> impl Iterator<Item = Position> {
-        SelectionIter {
-            curr: Position::new(0, 0),
-            end: self.end() - self.start(),
-        }
-    }
-}
-
-// Iterates over the points in a selection. The points it yields are relative to the start of the
-// selection, to make it easier to calculate the destination point when copy/pasting.
-struct SelectionIter {
-    curr: Position,
-    end: Position,
-}
-
-impl Iterator for SelectionIter {
-    type Item = Position;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.curr.column > self.end.column {
-            return None;
-        }
-        let curr = &mut self.curr;
-        let pos = *curr;
-        curr.line += 1;
-        if curr.line > self.end.line {
-            curr.line = 0;
-            curr.column += 1;
-        }
-        Some(pos)
+        let (track, column) = std::cmp::max(
+            (self.start.track, self.start.column),
+            (self.end.track, self.end.column),
+        );
+        Position::new(line, track, column)
     }
 }
 
@@ -553,10 +956,7 @@ mod tests {
     use super::*;
 
     fn pos(track: usize, line: usize) -> Position {
-        Position {
-            column: track * INPUTS_PER_STEP,
-            line,
-        }
+        Position::new(line, track, 0)
     }
 
     #[derive(Default)]
@@ -574,16 +974,14 @@ mod tests {
 
     impl Into<super::Step> for Step {
         fn into(self) -> super::Step {
-            super::Step {
-                cells: [
-                    self.pitch,
-                    self.instr,
-                    self.effects[0].cmd,
-                    self.effects[0].val,
-                    self.effects[1].cmd,
-                    self.effects[1].val,
-                ],
-            }
+            let mut cells = [None; STEP_CELLS];
+            cells[PITCH] = self.pitch;
+            cells[INSTR] = self.instr;
+            cells[FX_CMD1] = self.effects[0].cmd;
+            cells[FX_VAL1] = self.effects[0].val;
+            cells[FX_CMD2] = self.effects[1].cmd;
+            cells[FX_VAL2] = self.effects[1].val;
+            super::Step { cells }
         }
     }
 
@@ -629,6 +1027,47 @@ mod tests {
         assert_eq!(vec![0, 1], tracks);
     }
 
+    #[test]
+    fn insert_and_remove_tracks() {
+        let mut pattern = Pattern::new(2);
+        pattern.set_len(16);
+        *pattern.step_mut(pos(1, 0)) = Step::default().pitch(60).into();
+
+        pattern.insert_track(1);
+        assert_eq!(3, pattern.tracks.len());
+        assert_eq!(16, pattern.steps(1).len());
+        assert_eq!(None, pattern.steps(1)[0].pitch(0));
+        assert_eq!(Some(60), pattern.steps(2)[0].pitch(0));
+
+        pattern.remove_track(0);
+        pattern.remove_track(0);
+        assert_eq!(Some(60), pattern.steps(0)[0].pitch(0));
+        pattern.remove_track(0);
+        assert_eq!(1, pattern.tracks.len());
+    }
+
+    #[test]
+    fn map_instruments() {
+        let instr = |track, line| Position::new(line, track, INSTR);
+        let mut pattern = Pattern::new(2);
+        pattern.set_len(4);
+        pattern.set_key(instr(0, 0), 4, '3');
+        pattern.set_key(instr(1, 1), 4, '7');
+        *pattern.step_mut(pos(1, 2)) = Step::default().pitch(60).into();
+
+        pattern.map_instruments(|i| if i == 3 { None } else { Some(i + 1) });
+        assert_eq!(None, pattern.steps(0)[0].instrument(0));
+        assert_eq!(Some(8), pattern.steps(1)[1].instrument(0));
+        assert_eq!(None, pattern.steps(1)[2].instrument(0));
+        assert_eq!(Some(60), pattern.steps(1)[2].pitch(0));
+
+        // Instrument numbers have to be below MAX_INSTRUMENTS, so the last digit of 255 is rejected
+        pattern.set_key(instr(0, 3), 4, '2');
+        pattern.set_key(instr(0, 3), 4, '5');
+        pattern.set_key(instr(0, 3), 4, '5');
+        assert_eq!(Some(25), pattern.steps(0)[3].instrument(0));
+    }
+
     #[test]
     fn max_velocity() {
         let mut pattern = Pattern::new(1);
@@ -702,6 +1141,274 @@ mod tests {
         assert_eq!(1, notes.len());
     }
 
+    #[test]
+    fn retrigger() {
+        let mut pattern = Pattern::new(1);
+        let step = pattern.step_mut(pos(0, 0));
+        *step = Step::default()
+            .pitch(60)
+            .effect_cmd(0, FX_RETRIGGER)
+            .effect_val(0, 4)
+            .into();
+
+        let ticks: Vec<usize> = (0..TICKS_PER_LINE)
+            .filter(|tick| pattern.events(*tick).count() > 0)
+            .collect();
+        assert_eq!(vec![0, 3, 6, 9], ticks);
+        assert_eq!(Note::On(60, 100), pattern.events(9).next().unwrap().note);
+    }
+
+    #[test]
+    fn retrigger_with_offset_and_ramp() {
+        let mut pattern = Pattern::new(1);
+        let step = pattern.step_mut(pos(0, 0));
+        *step = Step::default()
+            .pitch(60)
+            .effect_cmd(0, FX_RETRIGGER)
+            .effect_val(0, 32)
+            .effect_cmd(1, FX_OFFSET)
+            .effect_val(1, 6)
+            .into();
+
+        let notes: Vec<(usize, Note)> = (0..TICKS_PER_LINE)
+            .flat_map(|tick| pattern.events(tick).map(move |n| (tick, n.note)))
+            .collect();
+        assert_eq!(
+            vec![
+                (6, Note::On(60, 100)),
+                (8, Note::On(60, 70)),
+                (10, Note::On(60, 40))
+            ],
+            notes
+        );
+
+        // Notes can't be retriggered more often than once per tick
+        let step = pattern.step_mut(pos(0, 0));
+        *step = Step::default()
+            .pitch(60)
+            .effect_cmd(0, FX_RETRIGGER)
+            .effect_val(0, 120)
+            .effect_cmd(1, FX_OFFSET)
+            .effect_val(1, 9)
+            .into();
+        let ticks: Vec<usize> = (0..TICKS_PER_LINE)
+            .filter(|tick| pattern.events(*tick).count() > 0)
+            .collect();
+        assert_eq!(vec![9, 10, 11], ticks);
+    }
+
+    #[test]
+    fn note_cut() {
+        let mut pattern = Pattern::new(1);
+        pattern.set_note_columns(0, 2);
+        let step = pattern.step_mut(pos(0, 0));
+        *step = Step::default()
+            .pitch(60)
+            .effect_cmd(0, FX_CUT)
+            .effect_val(0, 5)
+            .effect_cmd(1, FX_OFFSET)
+            .effect_val(1, 2)
+            .into();
+
+        let notes: Vec<(usize, usize, Note)> = (0..TICKS_PER_LINE)
+            .flat_map(|tick| pattern.events(tick).map(move |n| (tick, n.column, n.note)))
+            .collect();
+        assert_eq!(
+            vec![
+                (2, 0, Note::On(60, 100)),
+                (7, 0, Note::Off),
+                (7, 1, Note::Off)
+            ],
+            notes
+        );
+    }
+
+    #[test]
+    fn note_cut_with_retrigger() {
+        let mut pattern = Pattern::new(1);
+        let step = pattern.step_mut(pos(0, 0));
+        *step = Step::default()
+            .pitch(60)
+            .effect_cmd(0, FX_RETRIGGER)
+            .effect_val(0, 3)
+            .effect_cmd(1, FX_CUT)
+            .effect_val(1, 2)
+            .into();
+
+        let offs: Vec<usize> = (0..TICKS_PER_LINE)
+            .filter(|tick| pattern.events(*tick).any(|n| n.note == Note::Off))
+            .collect();
+        assert_eq!(vec![2, 6, 10], offs);
+
+        // Notes are retriggered before they would be cut
+        let step = pattern.step_mut(pos(0, 0));
+        *step = Step::default()
+            .pitch(60)
+            .effect_cmd(0, FX_RETRIGGER)
+            .effect_val(0, 3)
+            .effect_cmd(1, FX_CUT)
+            .effect_val(1, 4)
+            .into();
+        let offs =
+            (0..TICKS_PER_LINE).filter(|tick| pattern.events(*tick).any(|n| n.note == Note::Off));
+        assert_eq!(0, offs.count());
+    }
+
+    #[test]
+    fn slide() {
+        let mut pattern = Pattern::new(1);
+        let step = pattern.step_mut(pos(0, 0));
+        *step = Step::default()
+            .pitch(60)
+            .effect_cmd(0, FX_SLIDE_DOWN)
+            .effect_val(0, 1)
+            .into();
+
+        let notes: Vec<Note> = (0..TICKS_PER_LINE)
+            .flat_map(|tick| pattern.events(tick).map(|n| n.note))
+            .collect();
+        assert_eq!(Note::On(60, 100), notes[0]);
+        assert_eq!(Note::Slide(-8), notes[1]);
+        let cents: i16 = notes
+            .iter()
+            .map(|note| match note {
+                Note::Slide(cents) => *cents,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(-100, cents);
+    }
+
+    #[test]
+    fn glide() {
+        let mut pattern = Pattern::new(1);
+        let step = pattern.step_mut(pos(0, 0));
+        *step = Step::default()
+            .pitch(60)
+            .effect_cmd(0, FX_GLIDE)
+            .effect_val(0, 3)
+            .effect_cmd(1, FX_OFFSET)
+            .effect_val(1, 2)
+            .into();
+
+        let notes: Vec<(usize, Note)> = (0..TICKS_PER_LINE)
+            .flat_map(|tick| pattern.events(tick).map(move |n| (tick, n.note)))
+            .collect();
+        assert_eq!(
+            vec![
+                (2, Note::Glide(60, 3)),
+                (3, Note::Glide(60, 2)),
+                (4, Note::Glide(60, 1))
+            ],
+            notes
+        );
+    }
+
+    #[test]
+    fn arpeggio() {
+        let mut pattern = Pattern::new(1);
+        let step = pattern.step_mut(pos(0, 0));
+        *step = Step::default()
+            .pitch(60)
+            .effect_cmd(0, FX_ARPEGGIO)
+            .effect_val(0, 37)
+            .into();
+
+        let notes: Vec<(usize, Note)> = (0..TICKS_PER_LINE)
+            .flat_map(|tick| pattern.events(tick).map(move |n| (tick, n.note)))
+            .collect();
+        assert_eq!(
+            vec![
+                (0, Note::On(60, 100)),
+                (4, Note::On(63, 100)),
+                (8, Note::On(67, 100))
+            ],
+            notes
+        );
+    }
+
+    #[test]
+    fn arpeggio_with_chord() {
+        let mut pattern = Pattern::new(1);
+        let step = pattern.step_mut(pos(0, 0));
+        *step = Step::default()
+            .pitch(60)
+            .effect_cmd(0, FX_CHORD)
+            .effect_val(0, 47)
+            .effect_cmd(1, FX_ARPEGGIO)
+            .effect_val(1, 2)
+            .into();
+
+        let notes: Vec<u8> = (0..TICKS_PER_LINE)
+            .flat_map(|tick| pattern.events(tick))
+            .map(|n| match n.note {
+                Note::On(pitch, _) => pitch,
+                _ => 0,
+            })
+            .collect();
+        assert_eq!(vec![60, 64, 67, 60, 64, 67], notes);
+    }
+
+    #[test]
+    fn arpeggio_rate() {
+        let mut pattern = Pattern::new(1);
+        let step = pattern.step_mut(pos(0, 0));
+        *step = Step::default()
+            .pitch(MAX_PITCH - 2)
+            .effect_cmd(0, FX_ARPEGGIO)
+            .effect_val(0, 15)
+            .effect_cmd(1, FX_ARPEGGIO)
+            .effect_val(1, 3)
+            .into();
+
+        let notes: Vec<(usize, Note)> = (0..TICKS_PER_LINE)
+            .flat_map(|tick| pattern.events(tick).map(move |n| (tick, n.note)))
+            .collect();
+        let (root, top) = (MAX_PITCH - 2, MAX_PITCH - 1);
+        assert_eq!(
+            vec![
+                (0, Note::On(root, 100)),
+                (3, Note::On(top, 100)),
+                (6, Note::On(top, 100)),
+                (9, Note::On(root, 100))
+            ],
+            notes
+        );
+    }
+
+    #[test]
+    fn conditions() {
+        let condition = |value| {
+            let step: super::Step = Step::default()
+                .effect_cmd(1, FX_CONDITION)
+                .effect_val(1, value)
+                .into();
+            step.condition()
+        };
+        assert_eq!(Some(Condition::First), condition(1));
+        assert_eq!(Some(Condition::NotFill), condition(4));
+        assert_eq!(Some(Condition::Every(2, 4)), condition(24));
+        assert_eq!(None, condition(42));
+        assert_eq!(None, condition(5));
+
+        let step: super::Step = Step::default()
+            .effect_cmd(0, FX_PROBABILITY)
+            .effect_val(0, 150)
+            .into();
+        assert_eq!(Some(100), step.probability());
+
+        let passes = |condition: Condition| -> Vec<u32> {
+            (0..8)
+                .filter(|pass| condition.is_met(*pass, false))
+                .collect()
+        };
+        assert_eq!(vec![1, 5], passes(Condition::Every(2, 4)));
+        assert_eq!(vec![0], passes(Condition::First));
+        assert_eq!(7, passes(Condition::NotFirst).len());
+        assert!(Condition::Fill.is_met(0, true));
+        assert!(!Condition::Fill.is_met(0, false));
+    }
+
     #[test]
     fn chord() {
         let mut pattern = Pattern::new(1);
@@ -747,17 +1454,90 @@ mod tests {
     }
 
     #[test]
-    fn selection_iter() {
-        let a = Position::new(2, 2);
-        let b = Position::new(3, 3);
-        let s = Selection::new(a, b);
-        let expected = vec![
-            Position::new(0, 0),
-            Position::new(1, 0),
-            Position::new(0, 1),
-            Position::new(1, 1),
-        ];
-        assert_eq!(expected, s.iter().collect::<Vec<Position>>());
+    fn note_columns() {
+        let mut pattern = Pattern::new(2);
+        pattern.set_note_columns(0, 2);
+        assert_eq!(10, pattern.inputs(0));
+        assert_eq!(7, pattern.inputs(1));
+
+        // Pitch, instrument and volume of the second note column
+        pattern.set_key(Position::new(0, 0, 0), 4, 'z');
+        pattern.set_key(Position::new(0, 0, 3), 4, 'c');
+        pattern.set_key(Position::new(0, 0, 4), 4, '2');
+        pattern.set_key(Position::new(0, 0, 5), 4, '9');
+        pattern.set_key(Position::new(0, 0, 5), 4, '0');
+        assert!(pattern.is_pitch_input(Position::new(0, 0, 3)));
+        assert!(!pattern.is_pitch_input(Position::new(0, 0, 6)));
+
+        // Effects apply to all note columns
+        pattern.set_key(Position::new(0, 0, 6), 4, 'C');
+        pattern.set_key(Position::new(0, 0, 7), 4, '7');
+
+        let notes: Vec<(usize, usize, Note)> = pattern
+            .events(0)
+            .map(|n| (n.column, n.instrument, n.note))
+            .collect();
+        assert_eq!(
+            vec![
+                (0, 0, Note::On(48, 100)),
+                (0, 0, Note::On(55, 100)),
+                (1, 2, Note::On(52, 90)),
+                (1, 2, Note::On(59, 90)),
+            ],
+            notes
+        );
+
+        let mut other = Pattern::new(2);
+        assert!(!other.has_layout_of(&pattern));
+        other.copy_layout(&pattern);
+        assert!(other.has_layout_of(&pattern));
+
+        // Removing a note column clears its notes
+        pattern.set_note_columns(0, 1);
+        pattern.set_note_columns(0, 2);
+        assert_eq!(None, pattern.steps(0)[0].pitch(1));
+        assert_eq!(Some(48), pattern.steps(0)[0].pitch(0));
+    }
+
+    #[test]
+    fn selection_across_tracks() {
+        let s = Selection::new(Position::new(3, 1, 2), Position::new(1, 0, 5));
+        assert!(s.contains(Position::new(2, 0, 6)));
+        assert!(s.contains(Position::new(2, 1, 0)));
+        assert!(!s.contains(Position::new(2, 0, 4)));
+        assert!(!s.contains(Position::new(2, 1, 3)));
+        assert!(!s.contains(Position::new(4, 1, 0)));
+        assert_eq!(0..=1, s.tracks());
+    }
+
+    #[test]
+    fn copy_between_note_columns() {
+        let mut src = Pattern::new(2);
+        src.set_note_columns(0, 2);
+        src.set_key(Position::new(0, 0, 0), 4, 'z');
+        src.set_key(Position::new(0, 0, 3), 4, 'c');
+        src.set_key(Position::new(0, 0, 6), 4, 'V');
+
+        // The second note column doesn't fit in the destination track, the effects still line up
+        let mut dst = Pattern::new(2);
+        let s = Selection::new(Position::new(0, 0, 0), Position::new(0, 0, 9));
+        dst.copy(Position::new(0, 0, 0), &src, &s);
+        assert_eq!(Some(48), dst.steps(0)[0].pitch(0));
+        assert_eq!(Some(b'V'), dst.steps(0)[0].effect_cmd(0));
+        assert!(dst.steps(1)[0].is_empty());
+
+        // Across tracks, the effects of the first track and the notes of the next one line up
+        let s = Selection::new(Position::new(0, 0, 6), Position::new(0, 1, 0));
+        let mut wide = src.clone();
+        wide.set_key(Position::new(0, 1, 0), 4, 'x');
+        dst.copy(Position::new(2, 0, 3), &wide, &s);
+        assert_eq!(Some(b'V'), dst.steps(0)[2].effect_cmd(0));
+        assert_eq!(Some(50), dst.steps(1)[2].pitch(0));
+
+        // Misaligned start
+        let mut dst = Pattern::new(2);
+        dst.copy(Position::new(0, 0, 1), &src, &s);
+        assert!(dst.steps(0)[0].is_empty());
     }
 
     #[test]
@@ -766,9 +1546,9 @@ mod tests {
         p1.set_len(16);
         let p2 = p1.clone();
 
-        let s = Selection::new(Position::new(0, 0), Position::new(8, 0));
+        let s = Selection::new(Position::new(0, 0, 0), Position::new(8, 0, 0));
         // No assertions but this panics without the bounds checking
-        p1.copy(Position::new(8, 0), &p2, &s);
+        p1.copy(Position::new(8, 0, 0), &p2, &s);
     }
 
     #[test]
@@ -777,7 +1557,7 @@ mod tests {
         p1.set_len(16);
         let p2 = p1.clone();
 
-        let s = Selection::new(Position::new(0, 0), Position::new(0, 11));
-        p1.copy(Position::new(0, 6), &p2, &s);
+        let s = Selection::new(Position::new(0, 0, 0), Position::new(0, 1, 6));
+        p1.copy(Position::new(0, 1, 0), &p2, &s);
     }
 }
@@ -0,0 +1,311 @@
+//! Project files are stored as pretty printed JSON. The top level object looks like this:
+//!
+//! ```text
+//! {
+//!   "version": 1,
+//!   "bpm": 120,
+//!   "lines_per_beat": 4,
+//!   "song": [0, 1, 1, 2],            // pattern ids in playback order
+//!   "loop_range": [0, 1],            // or null
+//!   "patterns": [
+//!     {
+//!       "id": 0,
+//!       "name": "Intro",            // or null
+//!       "color": [255, 0, 0],
+//!       "len": 32,
+//!       "note_columns": [1, 2],        // per instrument track
+//!       // one entry per instrument track, only non-empty steps are stored
+//!       "tracks": [[{ "line": 0, "cells": [48, 0, null, null, null, null, 90] }], []]
+//!     }
+//!   ],
+//!   // one entry per instrument slot, at most 255
+//!   "instruments": [
+//!     { "name": "Kick", "path": "/path/to/kick.wav", "params": { "Envelope Attack": 1.0 } },
+//!     null
+//!   ],
+//!   // instrument tracks, followed by the buses and the master track
+//!   "tracks": [
+//!     {
+//!       "name": "Drums",            // or null
+//!       "color": [0, 128, 255],            // or null for the default header color
+//!       "bus": false,
+//!       "solo_safe": false,            // only used for buses
+//!       "output": null,                // index of a bus track, null for the master track
+//!       "sends": [2, null],            // index of the return bus for each send
+//!       "params": { "Volume": -6.0, "Mute": 1.0 },
+//!       // insert effects in processing order
+//!       "effects": [{ "name": "Ducker", "bypass": false, "sidechain": 0, "params": {} }]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Step cells are stored in the order pitch, instrument, fx1 command, fx1 value, fx2 command, fx2
+//! value and volume, followed by pitch, instrument and volume of the other note columns. Trailing
+//! empty cells are left out. Parameters are stored by label, using the unmapped target value of the parameter.
+//! Parameters that are missing from the file keep their default value.
+//!
+//! The version is bumped whenever the format changes in a way older versions can't read.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use ratatui::style::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::engine::NUM_SENDS;
+use crate::params::{ParamIterExt, Params};
+use crate::pattern::{Pattern, Step, MAX_NOTE_COLUMNS, STEP_CELLS};
+
+pub const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct Project {
+    pub version: u32,
+    pub bpm: u16,
+    pub lines_per_beat: u16,
+    pub song: Vec<u64>,
+    pub loop_range: Option<(usize, usize)>,
+    pub patterns: Vec<PatternData>,
+    pub instruments: Vec<Option<InstrumentData>>,
+    pub tracks: Vec<TrackData>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PatternData {
+    pub id: u64,
+    #[serde(default)]
+    pub name: Option<String>,
+    pub color: [u8; 3],
+    pub len: usize,
+    /// Number of note columns of each track, missing entries default to one
+    #[serde(default)]
+    pub note_columns: Vec<usize>,
+    pub tracks: Vec<Vec<StepData>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StepData {
+    pub line: usize,
+    /// Trailing empty cells are left out
+    pub cells: Vec<Option<u8>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InstrumentData {
+    /// Defaults to the file name
+    #[serde(default)]
+    pub name: Option<String>,
+    pub path: Utf8PathBuf,
+    pub params: ParamData,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TrackData {
+    pub name: Option<String>,
+    /// Color of the track header in the editor
+    #[serde(default)]
+    pub color: Option<[u8; 3]>,
+    pub bus: bool,
+    #[serde(default)]
+    pub solo_safe: bool,
+    /// Index of the bus the track is routed to, or null for the master track
+    #[serde(default)]
+    pub output: Option<usize>,
+    /// Indices of the return buses fed by the sends of an instrument track
+    #[serde(default)]
+    pub sends: [Option<usize>; NUM_SENDS],
+    pub params: ParamData,
+    #[serde(default)]
+    pub effects: Vec<EffectData>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EffectData {
+    pub name: String,
+    pub bypass: bool,
+    /// Index of the track used as sidechain input
+    #[serde(default)]
+    pub sidechain: Option<usize>,
+    pub params: ParamData,
+}
+
+pub type ParamData = BTreeMap<String, f64>;
+
+impl PatternData {
+    pub fn new(id: u64, pattern: &Pattern) -> Self {
+        let color = match pattern.color {
+            Color::Rgb(r, g, b) => [r, g, b],
+            _ => [255, 255, 255],
+        };
+        let tracks = (0..pattern.tracks.len())
+            .map(|i| {
+                pattern
+                    .steps(i)
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, step)| !step.is_empty())
+                    .map(|(line, step)| {
+                        let len = step.cells().iter().rposition(Option::is_some).unwrap() + 1;
+                        StepData {
+                            line,
+                            cells: step.cells()[..len].to_vec(),
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            id,
+            name: pattern.name.clone(),
+            color,
+            len: pattern.len(),
+            note_columns: (0..pattern.tracks.len())
+                .map(|i| pattern.note_columns(i))
+                .collect(),
+            tracks,
+        }
+    }
+
+    pub fn to_pattern(&self) -> Result<Pattern> {
+        let mut tracks = Vec::with_capacity(self.tracks.len());
+        for steps in &self.tracks {
+            let mut track = vec![Step::default(); self.len];
+            for step in steps {
+                let dst = track
+                    .get_mut(step.line)
+                    .ok_or_else(|| anyhow!("pattern {}: invalid line {}", self.id, step.line))?;
+                if step.cells.len() > STEP_CELLS {
+                    return Err(anyhow!("pattern {}: invalid step", self.id));
+                }
+                let mut cells = [None; STEP_CELLS];
+                cells[..step.cells.len()].copy_from_slice(&step.cells);
+                *dst = Step::from(cells);
+                if !dst.is_valid() {
+                    return Err(anyhow!("pattern {}: invalid step", self.id));
+                }
+            }
+            tracks.push(track);
+        }
+        let [r, g, b] = self.color;
+        let mut pattern = Pattern::with_steps(Color::Rgb(r, g, b), tracks);
+        pattern.name = self.name.clone();
+        for (idx, note_columns) in self.note_columns.iter().enumerate() {
+            if idx >= pattern.tracks.len() || *note_columns > MAX_NOTE_COLUMNS {
+                return Err(anyhow!("pattern {}: invalid note columns", self.id));
+            }
+            pattern.set_note_columns(idx, *note_columns);
+        }
+        Ok(pattern)
+    }
+}
+
+pub fn param_data(params: &Arc<dyn Params>) -> ParamData {
+    params
+        .iter()
+        .map(|p| (p.label().to_string(), p.target()))
+        .collect()
+}
+
+pub fn apply_param_data(params: &Arc<dyn Params>, data: &ParamData) {
+    for param in params.iter() {
+        if let Some(value) = data.get(param.label()) {
+            param.set(*value);
+        }
+    }
+}
+
+pub fn read(path: &Utf8Path) -> Result<Project> {
+    let contents = fs::read_to_string(path)?;
+    let project: Project = serde_json::from_str(&contents)?;
+    if project.version != VERSION {
+        return Err(anyhow!(
+            "{}: unsupported project version {}",
+            path,
+            project.version
+        ));
+    }
+    Ok(project)
+}
+
+pub fn write(path: &Utf8Path, project: &Project) -> Result<()> {
+    let contents = serde_json::to_string_pretty(project)?;
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::MAX_PITCH;
+
+    #[test]
+    fn pattern_roundtrip() {
+        let mut cells = [None; STEP_CELLS];
+        cells[..4].copy_from_slice(&[Some(48), Some(1), Some(b'V'), Some(80)]);
+        // Pitch of the second note column
+        cells[7] = Some(52);
+        let mut tracks = vec![vec![Step::default(); 16]; 2];
+        tracks[1][3] = Step::from(cells);
+        let mut pattern = Pattern::with_steps(Color::Rgb(1, 2, 3), tracks);
+        pattern.name = Some(String::from("Chorus"));
+        pattern.set_note_columns(1, 2);
+
+        let data = PatternData::new(7, &pattern);
+        assert_eq!(0, data.tracks[0].len());
+        assert_eq!(1, data.tracks[1].len());
+
+        let json = serde_json::to_string(&data).unwrap();
+        let data: PatternData = serde_json::from_str(&json).unwrap();
+        let pattern = data.to_pattern().unwrap();
+        assert_eq!(16, pattern.len());
+        assert_eq!(Color::Rgb(1, 2, 3), pattern.color);
+        assert_eq!(Some("Chorus"), pattern.name.as_deref());
+        assert_eq!(2, pattern.note_columns(1));
+        assert_eq!(Some(52), pattern.steps(1)[3].pitch(1));
+        assert_eq!(&cells, pattern.steps(1)[3].cells());
+        assert!(pattern.steps(0).iter().all(|step| step.is_empty()));
+    }
+
+    #[test]
+    fn step_out_of_bounds() {
+        let data = PatternData {
+            id: 0,
+            name: None,
+            color: [0, 0, 0],
+            len: 4,
+            note_columns: vec![],
+            tracks: vec![vec![StepData {
+                line: 4,
+                cells: vec![Some(48)],
+            }]],
+        };
+        assert!(data.to_pattern().is_err());
+    }
+
+    #[test]
+    fn invalid_cells() {
+        let pattern = |cells: Vec<Option<u8>>| PatternData {
+            id: 0,
+            name: None,
+            color: [0, 0, 0],
+            len: 4,
+            note_columns: vec![],
+            tracks: vec![vec![StepData { line: 0, cells }]],
+        };
+        assert!(pattern(vec![Some(MAX_PITCH)]).to_pattern().is_ok());
+        assert!(pattern(vec![Some(MAX_PITCH + 1)]).to_pattern().is_err());
+        assert!(pattern(vec![None, None, Some(b'1'), Some(0)])
+            .to_pattern()
+            .is_err());
+        // Volume of the first note column
+        assert!(
+            pattern(vec![None; 6].into_iter().chain([Some(200)]).collect())
+                .to_pattern()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+
+use anyhow::{anyhow, Result};
+use camino::Utf8Path;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use triple_buffer::Output;
+
+use crate::app::{self, App, AppState};
+use crate::audio::Stereo;
+use crate::engine::Engine;
+use crate::project::Project;
+use crate::{FRAMES_PER_BUFFER, SAMPLE_RATE};
+
+// Upper bound for the release tail, in case a device never becomes idle
+const MAX_TAIL_SECONDS: usize = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderRange {
+    Song,
+    Loop,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderFormat {
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl RenderFormat {
+    fn spec(&self) -> WavSpec {
+        let (bits_per_sample, sample_format) = match self {
+            RenderFormat::Int16 => (16, SampleFormat::Int),
+            RenderFormat::Int24 => (24, SampleFormat::Int),
+            RenderFormat::Float32 => (32, SampleFormat::Float),
+        };
+        WavSpec {
+            channels: 2,
+            sample_rate: SAMPLE_RATE as u32,
+            bits_per_sample,
+            sample_format,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderOptions {
+    pub range: RenderRange,
+    pub format: RenderFormat,
+    /// Seed for trigger probabilities, so renders of the same project are identical
+    pub seed: u64,
+    /// Render with fill mode enabled
+    pub fill: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            range: RenderRange::Song,
+            format: RenderFormat::Int24,
+            seed: 0,
+            fill: false,
+        }
+    }
+}
+
+impl RenderOptions {
+    /// Parse render options given on the command line, e.g. `loop 16`, `32f` or `seed=7`.
+    pub fn parse(args: &[&str]) -> Result<Self> {
+        let mut options = Self::default();
+        for arg in args {
+            match *arg {
+                "song" => options.range = RenderRange::Song,
+                "loop" => options.range = RenderRange::Loop,
+                "16" => options.format = RenderFormat::Int16,
+                "24" => options.format = RenderFormat::Int24,
+                "32f" | "float" => options.format = RenderFormat::Float32,
+                "fill" => options.fill = true,
+                _ if arg.starts_with("seed=") => {
+                    options.seed = arg["seed=".len()..]
+                        .parse()
+                        .map_err(|_| anyhow!("render: invalid seed {}", arg))?
+                }
+                _ => return Err(anyhow!("render: invalid option {}", arg)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Render the project to a wav file.
+pub fn render(project: Project, path: &Utf8Path, options: &RenderOptions) -> Result<()> {
+    let mut renderer = Renderer::new(project, options)?;
+    let mut file = WavFile::create(path, options.format)?;
+    renderer.run(|_, buf| file.write(buf))?;
+    file.finalize()
+}
+
+/// Render each instrument track to a separate wav file in `dir`, along with the master mix. The
+/// track files contain the output of the track after volume and mute, before it's mixed into the
+/// master track.
+pub fn render_stems(project: Project, dir: &Utf8Path, options: &RenderOptions) -> Result<()> {
+    let mut renderer = Renderer::new(project, options)?;
+    fs::create_dir_all(dir)?;
+
+    let mut stems = Vec::new();
+    for (i, track) in renderer.app.state.tracks.iter().enumerate() {
+        if track.is_bus() {
+            continue;
+        }
+        let name = match &track.name {
+            Some(name) => format!("{:02} {}.wav", i, name.replace(['/', '\\'], "-")),
+            None => format!("{:02}.wav", i),
+        };
+        stems.push((track.id, WavFile::create(&dir.join(name), options.format)?));
+    }
+    let mut master = WavFile::create(&dir.join("master.wav"), options.format)?;
+
+    renderer.run(|engine, buf| {
+        for (track_id, file) in &mut stems {
+            file.write(engine.track_output(*track_id, buf.len()))?;
+        }
+        master.write(buf)
+    })?;
+
+    for (_, file) in stems {
+        file.finalize()?;
+    }
+    master.finalize()
+}
+
+/// Runs a separate app and engine without an audio stream, processing audio as fast as possible.
+struct Renderer {
+    app: App,
+    app_state: Output<AppState>,
+    engine: Engine,
+}
+
+impl Renderer {
+    fn new(project: Project, options: &RenderOptions) -> Result<Self> {
+        let (start, end) = match options.range {
+            RenderRange::Song => (0, project.song.len() - 1),
+            RenderRange::Loop => project
+                .loop_range
+                .ok_or_else(|| anyhow!("render: no loop range"))?,
+        };
+
+        let (mut app, app_state, mut engine, _) = app::new()?;
+        app.load_project(project)?;
+        if options.range == RenderRange::Song {
+            // Play the patterns in order, without jumping back to the start of the loop
+            app.state.loop_range = None;
+        }
+        app.state.is_playing = true;
+        app.state.fill = options.fill;
+        app.publish();
+        engine.play_range(start, end);
+        engine.seed(options.seed);
+
+        Ok(Self {
+            app,
+            app_state,
+            engine,
+        })
+    }
+
+    /// Process audio until the end of the render range has been reached and all devices are
+    /// idle. The callback is called with the master output for each processed buffer.
+    fn run<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&Engine, &[Stereo]) -> Result<()>,
+    {
+        let mut buf = vec![Stereo::ZERO; FRAMES_PER_BUFFER];
+        let max_tail = MAX_TAIL_SECONDS * SAMPLE_RATE as usize;
+        let mut tail = 0;
+        loop {
+            self.engine.process(self.app_state.read(), &mut buf);
+            f(&self.engine, &buf)?;
+            buf.fill(Stereo::ZERO);
+
+            if self.engine.is_finished() {
+                if self.engine.is_idle() || tail >= max_tail {
+                    return Ok(());
+                }
+                tail += buf.len();
+            }
+        }
+    }
+}
+
+struct WavFile {
+    writer: WavWriter<BufWriter<File>>,
+    format: RenderFormat,
+    max: f32,
+}
+
+impl WavFile {
+    fn create(path: &Utf8Path, format: RenderFormat) -> Result<Self> {
+        let spec = format.spec();
+        Ok(Self {
+            writer: WavWriter::create(path, spec)?,
+            format,
+            max: (1i64 << (spec.bits_per_sample - 1)) as f32 - 1.0,
+        })
+    }
+
+    fn write(&mut self, buf: &[Stereo]) -> Result<()> {
+        for frame in buf {
+            for ch in 0..2 {
+                let sample = frame.channel(ch);
+                match self.format {
+                    RenderFormat::Float32 => self.writer.write_sample(sample)?,
+                    _ => {
+                        let sample = sample.clamp(-1.0, 1.0) * self.max;
+                        self.writer.write_sample(sample as i32)?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finalize(self) -> Result<()> {
+        self.writer.finalize()?;
+        Ok(())
+    }
+}
@@ -3,7 +3,7 @@ use crate::audio::{Buffer, Frame, Stereo};
 use crate::engine::{Event, Plugin, ProcessContext, ProcessStatus};
 use crate::env::{Envelope, State as EnvelopeState};
 use crate::params::{self, format_millis, Param, ParamInfo, Params};
-use crate::pattern::Note;
+use crate::pattern::{Note, MAX_PITCH};
 use crate::SAMPLE_RATE;
 use anyhow::Result;
 use camino::Utf8PathBuf;
@@ -13,6 +13,8 @@ use std::ops::Range;
 use std::sync::Arc;
 
 pub const ROOT_PITCH: u8 = 48;
+// Time constant for smoothing pitch bends between ticks
+const PITCH_SMOOTHING_MS: f64 = 10.0;
 
 #[derive(Params)]
 pub struct SamplerParams {
@@ -70,8 +72,16 @@ pub struct Voice {
     params: Arc<SamplerParams>,
     position: f32,
     state: VoiceState,
+    /// Note column of the track that started the voice
+    column: usize,
     pitch_ratio: f32,
+    /// Playback rate of the sample at its root pitch
+    rate: f32,
     pitch: u8,
+    /// Pitch bend in cents, smoothly moving towards `target_bend`
+    bend: f32,
+    target_bend: f32,
+    bend_coeff: f32,
     velocity: f32,
     env: Envelope,
     sample: Arc<Buffer>,
@@ -93,7 +103,12 @@ impl Voice {
             pitch: 0,
             velocity: 0.0,
             pitch_ratio: 0.,
+            rate: 0.,
+            bend: 0.,
+            target_bend: 0.,
+            bend_coeff: (1.0 - f64::exp(-1000.0 / (PITCH_SMOOTHING_MS * SAMPLE_RATE))) as f32,
             state: VoiceState::Free,
+            column: 0,
             env: Envelope::new(adsr),
             sample,
             gate: 0.0,
@@ -115,6 +130,10 @@ impl Voice {
             }
 
             *dst_frame += frame * self.velocity * self.env.value(self.gate) as f32;
+            if self.bend != self.target_bend {
+                self.bend = next_bend(self.bend, self.target_bend, self.bend_coeff);
+                self.pitch_ratio = pitch_ratio(self.pitch, self.bend, self.rate);
+            }
             self.position += self.pitch_ratio;
             if self.position >= sample.len() as f32 {
                 self.state = VoiceState::Free;
@@ -131,6 +150,37 @@ impl Voice {
     fn note_off(&mut self) {
         self.gate = 0.0;
     }
+
+    fn update_pitch_ratio(&mut self) {
+        self.pitch_ratio = pitch_ratio(self.pitch, self.bend, self.rate);
+    }
+
+    fn slide(&mut self, cents: i16) {
+        // Keep the pitch within the range of notes
+        let max = (MAX_PITCH as f32 - self.pitch as f32) * 100.0;
+        let min = -(self.pitch as f32) * 100.0;
+        self.target_bend = (self.target_bend + cents as f32).clamp(min, max);
+    }
+
+    fn glide(&mut self, pitch: u8, ticks: u8) {
+        let cents = (pitch as f32 - self.pitch as f32) * 100.0;
+        self.target_bend += (cents - self.target_bend) / ticks.max(1) as f32;
+    }
+}
+
+/// Move the bend one sample closer to its target
+fn next_bend(bend: f32, target: f32, coeff: f32) -> f32 {
+    let bend = bend + (target - bend) * coeff;
+    if (target - bend).abs() < 0.1 {
+        target
+    } else {
+        bend
+    }
+}
+
+fn pitch_ratio(pitch: u8, bend: f32, rate: f32) -> f32 {
+    let semitones = pitch as f32 - ROOT_PITCH as f32 + bend / 100.0;
+    f32::powf(2., semitones / 12.0) * rate
 }
 
 #[derive(Clone)]
@@ -210,18 +260,20 @@ impl Sampler {
         }
     }
 
-    fn note_on(&mut self, track_id: TrackId, pitch: u8, velocity: u8) {
+    fn note_on(&mut self, track_id: TrackId, column: usize, pitch: u8, velocity: u8) {
         if let Some(voice) = self.voices.iter_mut().find(|v| v.state == VoiceState::Free) {
             voice.gate = 1.0;
             voice.state = VoiceState::Busy(track_id);
+            voice.column = column;
             voice.env = Envelope::new(self.params.adsr());
             voice.pitch = pitch;
             voice.velocity =
                 params::db_to_amp(map(velocity.into(), (0.0, 127.0), (-60.0, 0.0))) as f32;
 
-            let pitch = pitch as i8 - ROOT_PITCH as i8;
-            voice.pitch_ratio = f32::powf(2., pitch as f32 / 12.0)
-                * (self.sound.sample_rate as f32 / SAMPLE_RATE as f32);
+            voice.rate = self.sound.sample_rate as f32 / SAMPLE_RATE as f32;
+            voice.bend = 0.0;
+            voice.target_bend = 0.0;
+            voice.update_pitch_ratio();
             voice.position = self.sound.offset as f32;
         } else {
             eprintln!("dropped event");
@@ -230,24 +282,29 @@ impl Sampler {
 
     fn send_event(&mut self, ev: &Event) {
         match ev.note {
-            Note::On(pitch, velocity) => self.note_on(ev.track_id, pitch, velocity),
-            Note::Off => {
-                for voice in &mut self.voices.iter_mut() {
-                    if let VoiceState::Busy(track_id) = voice.state {
-                        if track_id == ev.track_id {
-                            voice.note_off();
-                        }
-                    }
-                }
-            }
+            Note::On(pitch, velocity) => self.note_on(ev.track_id, ev.column, pitch, velocity),
+            Note::Off => self.column_voices(ev).for_each(Voice::note_off),
+            Note::Slide(cents) => self.column_voices(ev).for_each(|v| v.slide(cents)),
+            Note::Glide(pitch, ticks) => self.column_voices(ev).for_each(|v| v.glide(pitch, ticks)),
         }
     }
 
+    /// Voices playing in the track and note column of the event
+    fn column_voices<'a>(&'a mut self, ev: &'a Event) -> impl Iterator<Item = &'a mut Voice> {
+        self.voices.iter_mut().filter(|voice| {
+            voice.state == VoiceState::Busy(ev.track_id) && voice.column == ev.column
+        })
+    }
+
     fn process_block(&mut self, ctx: &mut ProcessContext, range: &Range<usize>) -> ProcessStatus {
         let mut status = ProcessStatus::Idle;
         for voice in &mut self.voices.iter_mut() {
             if let VoiceState::Busy(track_id) = voice.state {
-                let buf = ctx.track_buffer(track_id, range);
+                let Some(buf) = ctx.track_buffer(track_id, range) else {
+                    // The track was deleted while the voice was playing
+                    voice.state = VoiceState::Free;
+                    continue;
+                };
                 let voice_status = voice.process(buf);
                 if let ProcessStatus::Continue = voice_status {
                     status = voice_status
@@ -310,33 +367,79 @@ mod tests {
         let track1 = TrackId::new();
         let track2 = TrackId::new();
 
-        tracks.insert(track1, Box::new(Track::default()));
-        tracks.insert(track2, Box::new(Track::default()));
+        let collector = basedrop::Collector::new();
+        let handle = collector.handle();
+        tracks.insert(track1, basedrop::Owned::new(&handle, Track::default()));
+        tracks.insert(track2, basedrop::Owned::new(&handle, Track::default()));
         let sample = Stereo::new([0.5, 0.5]);
 
         let sound = Sound::new(vec![sample; 16], 0, 44100);
         let mut sampler = Sampler::new(sound);
         let note = Note::On(ROOT_PITCH, 127);
 
-        let ev = Event::new(8, track1, note);
+        let ev = Event::new(8, track1, 0, note);
         Plugin::send_event(&mut sampler, ev);
 
-        let ev = Event::new(16, track2, note);
+        let ev = Event::new(16, track2, 0, note);
         Plugin::send_event(&mut sampler, ev);
 
         let buf_size = 32;
         let mut ctx = ProcessContext::new(&mut tracks, buf_size);
         sampler.process(&mut ctx);
 
-        let buf = ctx.track_buffer(track1, &(0..buf_size));
+        let buf = ctx.track_buffer(track1, &(0..buf_size)).unwrap();
         assert_eq!(vec![Stereo::ZERO; 8], buf[0..8]);
         // TODO: check for the actual sample value here, but easier if we can disable
         // envelope.
         assert_ne!(vec![Stereo::ZERO; 16], buf[8..24]);
         assert_eq!(vec![Stereo::ZERO; 8], buf[24..32]);
 
-        let buf = ctx.track_buffer(track2, &(0..buf_size));
+        let buf = ctx.track_buffer(track2, &(0..buf_size)).unwrap();
         assert_eq!(vec![Stereo::ZERO; 16], buf[0..16]);
         assert_ne!(vec![Stereo::ZERO; 16], buf[16..32]);
     }
+
+    #[test]
+    fn glide_and_slide() {
+        let mut tracks = HashMap::new();
+        let track = TrackId::new();
+        let collector = basedrop::Collector::new();
+        tracks.insert(
+            track,
+            basedrop::Owned::new(&collector.handle(), Track::default()),
+        );
+
+        let sound = Sound::new(vec![Stereo::new([0.5, 0.5]); 44100], 0, 44100);
+        let mut sampler = Sampler::new(sound);
+        Plugin::send_event(
+            &mut sampler,
+            Event::new(0, track, 0, Note::On(ROOT_PITCH, 127)),
+        );
+        Plugin::send_event(
+            &mut sampler,
+            Event::new(0, track, 0, Note::Glide(ROOT_PITCH + 12, 2)),
+        );
+        // Notes in other columns aren't affected
+        Plugin::send_event(
+            &mut sampler,
+            Event::new(0, track, 1, Note::On(ROOT_PITCH, 127)),
+        );
+
+        let mut ctx = ProcessContext::new(&mut tracks, 64);
+        sampler.process(&mut ctx);
+        let voice = &sampler.voices[0];
+        assert_eq!(600.0, voice.target_bend);
+        assert!(voice.bend > 0.0 && voice.bend < 600.0);
+        assert!(voice.pitch_ratio > 1.0);
+        assert_eq!(1.0, sampler.voices[1].pitch_ratio);
+
+        Plugin::send_event(&mut sampler, Event::new(0, track, 0, Note::Slide(-1500)));
+        for _ in 0..8 {
+            let mut ctx = ProcessContext::new(&mut tracks, 512);
+            sampler.process(&mut ctx);
+        }
+        let voice = &sampler.voices[0];
+        assert_eq!(-900.0, voice.bend);
+        assert!((voice.pitch_ratio - f32::powf(2.0, -0.75)).abs() < 1e-6);
+    }
 }
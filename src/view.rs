@@ -1,5 +1,6 @@
 pub mod editor;
 
+use std::sync::Arc;
 use std::time::Duration;
 
 use camino::Utf8PathBuf;
@@ -14,8 +15,9 @@ use ratatui::{
 };
 
 use crate::app::App;
-use crate::params::ParamIterExt;
-use crate::pattern::{Pattern, Selection};
+use crate::effects::EffectType;
+use crate::params::{ParamIterExt, Params};
+use crate::pattern::{Pattern, Position, Selection};
 use crate::sampler;
 use crate::view::editor::EditorState;
 
@@ -35,9 +37,20 @@ pub enum ProjectTreeState {
     Instruments,
     Tracks,
     Devices(usize),
+    AddDevice(usize),
+    DeviceParams(usize, usize),
     InstrumentParams(usize),
 }
 
+/// Item whose name is being edited inline
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum RenameTarget {
+    Track(usize),
+    Instrument(usize),
+    /// Position of the pattern in the song
+    Pattern(usize),
+}
+
 pub struct View {
     pub focus: Focus,
     pub files: ListState,
@@ -45,11 +58,14 @@ pub struct View {
     pub params: ListState,
     pub tracks: ListState,
     pub devices: ListState,
+    pub effect_types: ListState,
     pub patterns: ListState,
     pub project_tree_state: ProjectTreeState,
     pub selection: Option<Selection>,
     pub clipboard: Option<(Pattern, Selection)>,
     pub command: String,
+    pub message: Option<String>,
+    pub rename: Option<(RenameTarget, String)>,
     pub editor: EditorState,
     frames: usize,
 }
@@ -64,15 +80,35 @@ impl View {
             params: list.clone(),
             tracks: list.clone(),
             devices: list.clone(),
+            effect_types: list.clone(),
             patterns: list.clone(),
             editor: EditorState::default(),
             focus: Focus::Editor,
             command: String::new(),
+            message: None,
+            rename: None,
             project_tree_state: ProjectTreeState::Instruments,
             selection: None,
             clipboard: None,
         }
     }
+
+    /// Go back to the top of the project tree and the start of the song, e.g. after loading a
+    /// project, as the tracks and instruments that were selected might not exist anymore
+    pub fn reset_selections(&mut self) {
+        self.project_tree_state = ProjectTreeState::Instruments;
+        for list in [
+            &mut self.instruments,
+            &mut self.params,
+            &mut self.tracks,
+            &mut self.devices,
+            &mut self.patterns,
+        ] {
+            list.select(Some(0));
+        }
+        self.editor.cursor = Position::default();
+        self.selection = None;
+    }
 }
 
 pub fn render(app: &App, view: &mut View, f: &mut Frame) {
@@ -129,6 +165,8 @@ pub fn render(app: &App, view: &mut View, f: &mut Frame) {
         let spans = Line::from(vec![Span::raw(":"), Span::raw(&*view.command)]);
         let paragraph = Paragraph::new(spans);
         f.render_widget(paragraph, command)
+    } else if let Some(message) = &view.message {
+        f.render_widget(Paragraph::new(message.as_str()), command)
     }
 
     let area = render_outer_block(f.buffer_mut(), status, Borders::TOP | Borders::BOTTOM);
@@ -203,27 +241,53 @@ fn render_patterns(app: &App, view: &mut View, f: &mut Frame, area: Rect) {
     f.render_stateful_widget(patterns, sections[1], &mut view.patterns);
 }
 
-fn render_status_line(app: &App, _view: &mut View, f: &mut Frame, area: Rect) {
+fn render_status_line(app: &App, view: &mut View, f: &mut Frame, area: Rect) {
+    let pattern_name = match view.rename {
+        Some((RenameTarget::Pattern(idx), _)) => editing_name(view, RenameTarget::Pattern(idx))
+            .map(|name| format!("{:02} {}", idx, name)),
+        _ => app.state.selected_pattern().name.clone(),
+    };
     let playback_position = format!(
-        " [ {:0width$} . {:0width$} ] ",
+        " [ {:0width$} . {:0width$} ] {}{}",
         app.engine_state.current_pattern,
         app.engine_state.current_line(),
+        if app.state.fill { "FILL " } else { "" },
+        pattern_name.unwrap_or_default(),
         width = 3
     );
     let paragraph = Paragraph::new(playback_position).alignment(Alignment::Left);
     f.render_widget(paragraph, area);
 
-    let paragraph = Paragraph::new("*Untitled*").alignment(Alignment::Center);
+    let title = app
+        .project_path
+        .as_ref()
+        .and_then(|path| path.file_name())
+        .unwrap_or("*Untitled*");
+    let paragraph = Paragraph::new(title).alignment(Alignment::Center);
     f.render_widget(paragraph, area);
 
+    let loudness = &app.engine_state.loudness;
     let settings = format!(
-        "BPM {}    LPB {}    Oct {}  ",
-        app.state.bpm, app.state.lines_per_beat, app.state.octave,
+        "M {}  S {}  I {} LUFS    BPM {}    LPB {}    Oct {}  ",
+        format_lufs(loudness.momentary),
+        format_lufs(loudness.short_term),
+        format_lufs(loudness.integrated),
+        app.state.bpm,
+        app.state.lines_per_beat,
+        app.state.octave,
     );
     let paragraph = Paragraph::new(settings).alignment(Alignment::Right);
     f.render_widget(paragraph, area);
 }
 
+pub fn format_lufs(lufs: f64) -> String {
+    if lufs.is_finite() {
+        format!("{:.1}", lufs)
+    } else {
+        String::from("-inf")
+    }
+}
+
 fn render_project_tree(app: &App, view: &mut View, f: &mut Frame, area: Rect) {
     let highlight_style = highlight_style(view, Focus::ProjectTree);
     match view.project_tree_state {
@@ -234,10 +298,23 @@ fn render_project_tree(app: &App, view: &mut View, f: &mut Frame, area: Rect) {
                 .iter()
                 .enumerate()
                 .map(|(i, track)| {
+                    let output = track
+                        .output
+                        .and_then(|id| app.state.track_idx(id))
+                        .map(|idx| format!(" -> {}", track_name(app, idx)))
+                        .unwrap_or_default();
+                    let solo = if track.solo { " [solo]" } else { "" };
+                    let solo_safe = if track.solo_safe { " [safe]" } else { "" };
+                    let name = editing_name(view, RenameTarget::Track(i))
+                        .or_else(|| track.name.clone())
+                        .unwrap_or_else(|| String::from("-"));
                     ListItem::new(Span::raw(format!(
-                        "  {:0width$} {}",
+                        "  {:0width$} {}{}{}{}",
                         i,
-                        track.name.as_ref().map_or("-", |n| n.as_str()),
+                        name,
+                        output,
+                        solo,
+                        solo_safe,
                         width = 2
                     )))
                 })
@@ -253,59 +330,86 @@ fn render_project_tree(app: &App, view: &mut View, f: &mut Frame, area: Rect) {
             f.render_stateful_widget(tracks, area, &mut view.tracks);
         }
         ProjectTreeState::Devices(track_idx) => {
-            let devices = &app.state.tracks[track_idx].effects;
-            let devices: Vec<ListItem> = devices
+            // The track might have been deleted, show the track list instead
+            let Some(track) = app.state.tracks.get(track_idx) else {
+                view.project_tree_state = ProjectTreeState::Tracks;
+                return render_project_tree(app, view, f, area);
+            };
+            let devices: Vec<ListItem> = track
+                .effects
                 .iter()
                 .enumerate()
                 .map(|(i, dev)| {
-                    ListItem::new(Span::raw(format!(" {:0width$} {}", i, dev.name, width = 2)))
+                    let style = if dev.bypass {
+                        Style::default().fg(Color::DarkGray)
+                    } else {
+                        Style::default()
+                    };
+                    let bypass = if dev.bypass { " (bypassed)" } else { "" };
+                    ListItem::new(Span::styled(
+                        format!(" {:0width$} {}{}", i, dev.name, bypass, width = 2),
+                        style,
+                    ))
                 })
                 .collect();
 
-            let track_name = app.state.tracks[track_idx]
-                .name
-                .clone()
-                .unwrap_or(format!("Track {track_idx}"));
             let devices = ListView::new(devices)
                 .block(
                     Block::default()
-                        .title(track_name)
+                        .title(track_name(app, track_idx))
                         .borders(Borders::ALL)
                         .border_style(Style::default().fg(BORDER_COLOR)),
                 )
                 .highlight_style(highlight_style);
             f.render_stateful_widget(devices, area, &mut view.devices);
         }
-        ProjectTreeState::InstrumentParams(instrument_idx) => {
-            let instrument = app.state.instruments[instrument_idx].as_ref().unwrap();
-            let params = app.params(instrument.id);
-
-            // TODO: maybe use a table here to align values?
-            let w = (area.width as f32 * 0.6) as usize;
-            let params: Vec<ListItem> = params
+        ProjectTreeState::AddDevice(_) => {
+            let effect_types: Vec<ListItem> = EffectType::ALL
                 .iter()
-                .enumerate()
-                .map(|(i, p)| {
-                    ListItem::new(Span::raw(format!(
-                        " {:0nwidth$} {:lwidth$} {}",
-                        i,
-                        p.label(),
-                        p.as_string(),
-                        nwidth = 2,
-                        lwidth = w
-                    )))
-                })
+                .map(|effect_type| ListItem::new(Span::raw(format!(" {}", effect_type.name()))))
                 .collect();
 
-            let params = ListView::new(params)
+            let effect_types = ListView::new(effect_types)
                 .block(
                     Block::default()
-                        .title(instrument.name.as_ref())
+                        .title("Add device")
                         .borders(Borders::ALL)
                         .border_style(Style::default().fg(BORDER_COLOR)),
                 )
                 .highlight_style(highlight_style);
-            f.render_stateful_widget(params, area, &mut view.params);
+            f.render_stateful_widget(effect_types, area, &mut view.effect_types);
+        }
+        ProjectTreeState::DeviceParams(track_idx, device_idx) => {
+            let Some(track) = app.state.tracks.get(track_idx) else {
+                view.project_tree_state = ProjectTreeState::Tracks;
+                return render_project_tree(app, view, f, area);
+            };
+            if let Some(device) = track.effects.get(device_idx) {
+                let params = app.params(device.id);
+                let title = if device.effect_type.has_sidechain() {
+                    let key = device
+                        .sidechain
+                        .and_then(|id| app.state.track_idx(id))
+                        .map_or(String::from("none"), |idx| track_name(app, idx));
+                    format!("{} <- {}", device.name, key)
+                } else {
+                    device.name.clone()
+                };
+                render_params(params, &title, view, f, area);
+            }
+        }
+        ProjectTreeState::InstrumentParams(instrument_idx) => {
+            let Some(instrument) = app
+                .state
+                .instruments
+                .get(instrument_idx)
+                .and_then(Option::as_ref)
+            else {
+                view.project_tree_state = ProjectTreeState::Instruments;
+                return render_project_tree(app, view, f, area);
+            };
+            let params = app.params(instrument.id);
+            render_params(params, &instrument.name, view, f, area);
         }
         ProjectTreeState::Instruments => {
             let instruments: Vec<ListItem> = app
@@ -320,11 +424,10 @@ fn render_project_tree(app: &App, view: &mut View, f: &mut Frame, area: Rect) {
                     } else {
                         Span::raw(" ")
                     };
-                    let name = instr
-                        .as_ref()
-                        .map(|instr| instr.name.as_ref())
-                        .unwrap_or("");
-                    let snd_desc = Span::raw(format!(" {:0width$} {}", i, name, width = 2));
+                    let name = editing_name(view, RenameTarget::Instrument(i))
+                        .or_else(|| instr.as_ref().map(|instr| instr.name.clone()))
+                        .unwrap_or_default();
+                    let snd_desc = Span::raw(format!(" {:03} {}", i, name));
                     ListItem::new(Line::from(vec![selected, snd_desc]))
                 })
                 .collect();
@@ -340,6 +443,58 @@ fn render_project_tree(app: &App, view: &mut View, f: &mut Frame, area: Rect) {
     };
 }
 
+/// Returns the name with a cursor if it's currently being edited
+fn editing_name(view: &View, target: RenameTarget) -> Option<String> {
+    match &view.rename {
+        Some((t, name)) if *t == target => Some(format!("{}_", name)),
+        _ => None,
+    }
+}
+
+fn track_name(app: &App, track_idx: usize) -> String {
+    app.state.tracks[track_idx]
+        .name
+        .clone()
+        .unwrap_or(format!("Track {track_idx}"))
+}
+
+fn render_params(
+    params: &Arc<dyn Params>,
+    title: &str,
+    view: &mut View,
+    f: &mut Frame,
+    area: Rect,
+) {
+    let highlight_style = highlight_style(view, Focus::ProjectTree);
+
+    // TODO: maybe use a table here to align values?
+    let w = (area.width as f32 * 0.6) as usize;
+    let params: Vec<ListItem> = params
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            ListItem::new(Span::raw(format!(
+                " {:0nwidth$} {:lwidth$} {}",
+                i,
+                p.label(),
+                p.as_string(),
+                nwidth = 2,
+                lwidth = w
+            )))
+        })
+        .collect();
+
+    let params = ListView::new(params)
+        .block(
+            Block::default()
+                .title(title)
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(BORDER_COLOR)),
+        )
+        .highlight_style(highlight_style);
+    f.render_stateful_widget(params, area, &mut view.params);
+}
+
 fn render_file_browser(app: &App, view: &mut View, f: &mut Frame, area: Rect) {
     let area = render_outer_block(f.buffer_mut(), area, Borders::ALL);
     let sections = Layout::default()
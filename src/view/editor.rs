@@ -1,8 +1,8 @@
 use std::ops::Range;
 
 use crate::app::{App, Track};
-use crate::engine::TrackParams;
-use crate::pattern::{Position, INPUTS_PER_STEP, MAX_PITCH};
+use crate::engine::{TrackParams, NUM_SENDS};
+use crate::pattern::{Position, MAX_PITCH, NOTE_INPUTS};
 use crate::view::{render_outer_block, Focus, View, BORDER_COLOR};
 
 use ratatui::layout::{Alignment, Constraint, Direction, Layout};
@@ -10,18 +10,25 @@ use ratatui::widgets::Paragraph;
 use ratatui::{
     buffer::Buffer,
     layout::Rect,
-    style::{Color, Style},
+    style::{Color, Modifier, Style},
     text::{Line, Span},
     widgets::{Block, Borders, Widget},
 };
 
-const TRACK_WIDTH: u16 = "| C#4 05 v 20 R-10 |".len() as u16;
+const NOTE_COLUMN_WIDTH: u16 = " C#4 005 100".len() as u16;
+const EFFECTS_WIDTH: u16 = " v 20 R-10 ".len() as u16;
 const BUS_TRACK_WIDTH: u16 = 12;
 const STEPS_WIDTH: u16 = " 256 ".len() as u16;
+// Leaves room for a meter of at least 6 rows above the readouts and controls
+const MIN_MIXER_HEIGHT: u16 = 14 + NUM_SENDS as u16;
+// Gain reduction in dB per row of the meter
+const GR_METER_STEP: u16 = 2;
 
 #[derive(Clone, Default)]
 pub struct EditorState {
     pub cursor: Position,
+    /// The send that's adjusted with Alt-+ and Alt-_
+    pub send: usize,
     line_offset: usize,
     track_offset: usize,
 }
@@ -29,7 +36,13 @@ pub struct EditorState {
 pub fn render(app: &App, view: &mut View, area: Rect, buf: &mut Buffer) {
     let sections = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Percentage(75), Constraint::Percentage(25)].as_ref())
+        .constraints(
+            [
+                Constraint::Percentage(75),
+                Constraint::Min(MIN_MIXER_HEIGHT),
+            ]
+            .as_ref(),
+        )
         .split(area);
 
     let pattern_area = sections[0];
@@ -41,10 +54,7 @@ pub fn render(app: &App, view: &mut View, area: Rect, buf: &mut Buffer) {
     let pattern = app.state.selected_pattern();
     let mut last_line = view.editor.line_offset + std::cmp::min(height, pattern.len());
 
-    let pattern_size = pattern.size();
-    let cursor = &mut view.editor.cursor;
-    cursor.line = usize::min(pattern_size.lines - 1, cursor.line);
-    cursor.column = usize::min(pattern_size.columns - 1, cursor.column);
+    view.editor.cursor = pattern.clamp(view.editor.cursor);
 
     if last_line > pattern.len() {
         // pattern length must have been changed so reset offset
@@ -60,15 +70,29 @@ pub fn render(app: &App, view: &mut View, area: Rect, buf: &mut Buffer) {
         last_line = view.editor.line_offset + height;
     }
 
-    let pattern_width = pattern_area.width - STEPS_WIDTH;
-    let num_tracks = ((pattern_width - BUS_TRACK_WIDTH) / TRACK_WIDTH) as usize;
-
-    let selected_track = view.editor.cursor.track();
-    if selected_track >= view.editor.track_offset + num_tracks {
-        view.editor.track_offset = selected_track + 1 - num_tracks;
-    } else if selected_track < view.editor.track_offset {
+    let pattern_width = pattern_area.width.saturating_sub(STEPS_WIDTH);
+    let num_buses = app
+        .state
+        .tracks
+        .iter()
+        .filter(|track| track.is_bus())
+        .count() as u16;
+    let tracks_width = pattern_width.saturating_sub(num_buses * BUS_TRACK_WIDTH);
+    let width_of = |idx: usize| track_width(pattern.note_columns(idx));
+
+    // Scroll until the selected track fits, tracks can have different widths
+    let selected_track = view.editor.cursor.track;
+    if selected_track < view.editor.track_offset {
         view.editor.track_offset = selected_track;
     }
+    while view.editor.track_offset < selected_track
+        && (view.editor.track_offset..=selected_track)
+            .map(width_of)
+            .sum::<u16>()
+            > tracks_width
+    {
+        view.editor.track_offset += 1;
+    }
 
     let left = area.left() + 1;
     let steps = view.editor.line_offset..last_line;
@@ -93,6 +117,7 @@ pub fn render(app: &App, view: &mut View, area: Rect, buf: &mut Buffer) {
     }
 
     let mut x = area.x + STEPS_WIDTH;
+    let selected_send = view.editor.send;
     let mut render_track = |x: u16, width: u16, track: &Track, idx: usize| {
         let mut borders = Borders::RIGHT | Borders::BOTTOM | Borders::LEFT;
 
@@ -110,10 +135,10 @@ pub fn render(app: &App, view: &mut View, area: Rect, buf: &mut Buffer) {
         } else {
             format!(" {}", idx)
         };
-        let bg_color = Color::Indexed(250);
+        let bg_color = track.color.unwrap_or(Color::Indexed(250));
         let header = Paragraph::new(track_name)
             .alignment(Alignment::Left)
-            .style(Style::default().bg(bg_color).fg(Color::Black));
+            .style(Style::default().bg(bg_color).fg(text_color(bg_color)));
 
         let header_area = Rect { height: 1, ..inner };
         header.render(header_area, buf);
@@ -132,31 +157,52 @@ pub fn render(app: &App, view: &mut View, area: Rect, buf: &mut Buffer) {
 
         borders |= Borders::TOP;
         let inner = render_outer_block(buf, area, borders);
-        render_mixer_controls(app, track, buf, inner, idx);
+        render_mixer_controls(app, track, buf, inner, idx, selected_send);
     };
 
+    let tracks_end = x + tracks_width;
     for (idx, track) in app.state.tracks.iter().enumerate() {
-        if track.is_bus() {
+        if track.is_bus() || idx < view.editor.track_offset {
             continue;
         }
-        if idx >= view.editor.track_offset && idx < view.editor.track_offset + num_tracks {
-            render_track(x, TRACK_WIDTH, track, idx);
-            x += TRACK_WIDTH;
+        let mut width = width_of(idx);
+        if x + width > tracks_end {
+            // The selected track is always drawn, even if it doesn't fit
+            if idx != view.editor.track_offset {
+                break;
+            }
+            width = tracks_end - x;
+            if width == 0 {
+                break;
+            }
         }
+        render_track(x, width, track, idx);
+        x += width;
     }
 
-    // Master track sticks to the right of the editor area
-    let master_track = app.state.tracks.last().unwrap();
-    let master_idx = app.state.tracks.len() - 1;
-    render_track(
-        area.x + (area.width - BUS_TRACK_WIDTH),
-        BUS_TRACK_WIDTH,
-        master_track,
-        master_idx,
-    );
+    // Buses stick to the right of the editor area, with the master track on the far right
+    let mut x = area.x + area.width;
+    let buses_start = area.x + STEPS_WIDTH;
+    for (idx, track) in app.state.tracks.iter().enumerate().rev() {
+        if track.is_bus() {
+            // Buses that don't fit on narrow terminals aren't drawn
+            if x < buses_start + BUS_TRACK_WIDTH {
+                break;
+            }
+            x -= BUS_TRACK_WIDTH;
+            render_track(x, BUS_TRACK_WIDTH, track, idx);
+        }
+    }
 }
 
-fn render_mixer_controls(app: &App, track: &Track, buf: &mut Buffer, area: Rect, idx: usize) {
+fn render_mixer_controls(
+    app: &App,
+    track: &Track,
+    buf: &mut Buffer,
+    area: Rect,
+    idx: usize,
+    selected_send: usize,
+) {
     let mut meter_width = 2;
     if area.width % 2 != 0 {
         meter_width += 1;
@@ -168,28 +214,32 @@ fn render_mixer_controls(app: &App, track: &Track, buf: &mut Buffer, area: Rect,
         x: area.x + offset,
         y: area.y,
         width: meter_width,
-        height: area.height - 4,
+        height: area.height.saturating_sub(6 + NUM_SENDS as u16),
     };
 
+    let rms = track.rms();
+    let peak = track.peak();
     let mut db = 0;
     for i in 0..meter.height {
-        let rms = track.rms();
-        let meter_color = |value: f32| {
+        // The RMS level fills the meter, the held peak level is marked above it
+        let meter_color = |rms: f32, peak: f32| {
             let db = db as f32;
-            if value > db {
-                if value < db + 2.0 {
+            if rms > db {
+                if rms < db + 2.0 {
                     Color::Indexed(34)
-                } else if value < db + 4.0 {
+                } else if rms < db + 4.0 {
                     Color::Indexed(40)
                 } else {
                     Color::Indexed(46)
                 }
+            } else if peak > db && (peak <= db + 6.0 || i == 0) {
+                Color::White
             } else {
                 Color::Gray
             }
         };
-        let left_color = meter_color(rms.0);
-        let right_color = meter_color(rms.1);
+        let left_color = meter_color(rms.0, peak.0);
+        let right_color = meter_color(rms.1, peak.1);
 
         let channel_width = meter_width / 2;
         let meter_symbol = "▇".repeat(channel_width.into());
@@ -204,10 +254,47 @@ fn render_mixer_controls(app: &App, track: &Track, buf: &mut Buffer, area: Rect,
         db -= 6;
     }
 
+    // Gain reduction meter, growing down from the top
+    if let Some(gain_reduction) = track.gain_reduction() {
+        let x = meter.x + meter_width + 1;
+        if x < area.right() {
+            for i in 0..meter.height {
+                let color = if gain_reduction > (i * GR_METER_STEP) as f32 {
+                    Color::Indexed(208)
+                } else {
+                    Color::DarkGray
+                };
+                buf.set_string(x, meter.y + i, "▌", Style::default().fg(color));
+            }
+        }
+    }
+
+    // Peak and RMS readout, highlighted when the track clipped
+    let readout_area = Rect {
+        x: area.x,
+        y: meter.bottom(),
+        width: area.width,
+        height: 1,
+    };
+    let readout = format!(
+        "{} {}",
+        format_db(f32::max(peak.0, peak.1), 1),
+        format_db(f32::max(rms.0, rms.1), 0)
+    );
+    let readout_style = if track.clipped() {
+        Style::default().bg(Color::Red).fg(Color::White)
+    } else {
+        Style::default()
+    };
+    Paragraph::new(readout)
+        .alignment(Alignment::Center)
+        .style(readout_style)
+        .render(readout_area, buf);
+
     // Volume control
     let volume_area = Rect {
         x: area.x,
-        y: meter.y + meter.height,
+        y: readout_area.bottom(),
         width: area.width,
         height: 2,
     };
@@ -221,9 +308,21 @@ fn render_mixer_controls(app: &App, track: &Track, buf: &mut Buffer, area: Rect,
         .block(block);
     volume.render(volume_area, buf);
 
+    let params = app.params(track.device_id);
+    let pan_area = Rect {
+        y: volume_area.bottom(),
+        height: 1,
+        ..volume_area
+    };
+    let pan = params.get_param(TrackParams::PAN).as_string();
+    let width = params.get_param(TrackParams::WIDTH).as_string();
+    Paragraph::new(format!("{} {}", pan, width))
+        .alignment(Alignment::Center)
+        .render(pan_area, buf);
+
     let button_area = Rect {
         x: area.x,
-        y: meter.y + meter.height + 2,
+        y: pan_area.bottom() + NUM_SENDS as u16,
         width: area.width,
         height: 2,
     };
@@ -233,9 +332,38 @@ fn render_mixer_controls(app: &App, track: &Track, buf: &mut Buffer, area: Rect,
         return;
     }
 
+    // Send levels, dimmed when the send isn't routed anywhere. The send that's adjusted from the
+    // editor is shown in bold.
+    for (i, bus) in track.sends.iter().enumerate() {
+        let level = params.get_param(TrackParams::SENDS[i]);
+        let mut text = format!("{} {}", (b'A' + i as u8) as char, level.as_string());
+        if params.get_param(TrackParams::SENDS_PRE[i]).as_bool() {
+            text.push_str(" pre");
+        }
+        let mut style = if bus.is_some() {
+            Style::default()
+        } else {
+            Style::default().fg(Color::DarkGray)
+        };
+        if i == selected_send {
+            style = style.add_modifier(Modifier::BOLD);
+        }
+        let send_area = Rect {
+            y: pan_area.bottom() + i as u16,
+            height: 1,
+            ..volume_area
+        };
+        Paragraph::new(text)
+            .alignment(Alignment::Center)
+            .style(style)
+            .render(send_area, buf);
+    }
+
     let muted = app.params(track.device_id).get_param(TrackParams::MUTE);
     let button_style = if muted.as_bool() {
         Style::default().bg(Color::DarkGray)
+    } else if track.solo {
+        Style::default().bg(Color::Cyan).fg(Color::Black)
     } else {
         Style::default().bg(Color::Yellow).fg(Color::Black)
     };
@@ -249,6 +377,14 @@ fn render_mixer_controls(app: &App, track: &Track, buf: &mut Buffer, area: Rect,
     button.render(button_area, buf);
 }
 
+fn format_db(db: f32, precision: usize) -> String {
+    if db < -60.0 {
+        String::from("-inf")
+    } else {
+        format!("{:.*}", precision, db)
+    }
+}
+
 fn render_track_steps(
     app: &App,
     view: &View,
@@ -257,60 +393,35 @@ fn render_track_steps(
     idx: usize,
     step_range: &Range<usize>,
 ) {
+    let note_columns = app.state.selected_pattern().note_columns(idx);
     let mut y = area.top() + 1;
     for (line, step) in app.state.pattern_steps(idx, step_range).iter().enumerate() {
         let line = line + step_range.start;
-        let column = idx * INPUTS_PER_STEP;
-
-        let pitch = match step.pitch() {
-            Some(pitch) => &NOTE_NAMES[pitch as usize],
-            None => "---",
-        };
-
-        let snd = match step.instrument() {
-            Some(v) => format!("{:0width$}", v, width = 2),
-            None => String::from("--"),
-        };
-
-        let fx_cmd1 = step
-            .effect_cmd(0)
-            .map(|c| (c as char).to_string())
-            .unwrap_or_else(|| "-".into());
-        let fx_val1 = step
-            .effect_val(0)
-            .map(|c| format!("{:3}", c))
-            .unwrap_or_else(|| "---".into());
-        let fx_cmd2 = step
-            .effect_cmd(1)
-            .map(|c| (c as char).to_string())
-            .unwrap_or_else(|| "-".into());
-        let fx_val2 = step
-            .effect_val(1)
-            .map(|c| format!("{:3}", c))
-            .unwrap_or_else(|| "---".into());
 
         let line_style = if line % app.state.lines_per_beat as usize == 0 {
             Style::default().bg(Color::Indexed(236))
         } else {
             Style::default()
         };
-        let input_style = |offset: usize| {
+        let input_style = |column: usize| {
             let selected = view
                 .selection
                 .as_ref()
-                .map(|s| s.contains(line, column + offset))
+                .map(|s| s.contains(Position::new(line, idx, column)))
                 .unwrap_or(false);
 
             if matches!(view.focus, Focus::Editor)
                 && view.editor.cursor.line == line
-                && view.editor.cursor.column == column + offset
+                && view.editor.cursor.track == idx
+                && view.editor.cursor.column == column
             {
                 Style::default().bg(Color::Green).fg(Color::Black)
             } else if selected {
                 Style::default().bg(Color::Rgb(65, 79, 139))
             } else if is_current_line(app, line)
-                && offset == 0
-                && step.pitch().is_some()
+                && column < note_columns * NOTE_INPUTS
+                && column.is_multiple_of(NOTE_INPUTS)
+                && step.pitch(column / NOTE_INPUTS).is_some()
                 && app.state.is_playing
             {
                 // Pitch input is highlighted when it's the currently active note
@@ -320,25 +431,79 @@ fn render_track_steps(
             }
         };
 
-        let spans = Line::from(vec![
-            Span::styled(" ", line_style),
-            Span::styled(pitch, input_style(0)),
-            Span::styled(" ", line_style),
-            Span::styled(snd, input_style(1)),
+        let mut spans = Vec::new();
+        for col in 0..note_columns {
+            let pitch = match step.pitch(col) {
+                Some(pitch) => &NOTE_NAMES[pitch as usize],
+                None => "---",
+            };
+            let snd = match step.instrument(col) {
+                Some(v) => format!("{:03}", v),
+                None => String::from("---"),
+            };
+            let volume = match step.volume(col) {
+                Some(v) => format!("{:3}", v),
+                None => String::from("---"),
+            };
+            let column = col * NOTE_INPUTS;
+            spans.extend([
+                Span::styled(" ", line_style),
+                Span::styled(pitch, input_style(column)),
+                Span::styled(" ", line_style),
+                Span::styled(snd, input_style(column + 1)),
+                Span::styled(" ", line_style),
+                Span::styled(volume, input_style(column + 2)),
+            ]);
+        }
+
+        let fx_cmd1 = step
+            .effect_cmd(0)
+            .map(|c| (c as char).to_string())
+            .unwrap_or_else(|| "-".into());
+        let fx_val1 = step
+            .effect_val(0)
+            .map(|c| format!("{:3}", c))
+            .unwrap_or_else(|| "---".into());
+        let fx_cmd2 = step
+            .effect_cmd(1)
+            .map(|c| (c as char).to_string())
+            .unwrap_or_else(|| "-".into());
+        let fx_val2 = step
+            .effect_val(1)
+            .map(|c| format!("{:3}", c))
+            .unwrap_or_else(|| "---".into());
+
+        let column = note_columns * NOTE_INPUTS;
+        spans.extend([
             Span::styled(" ", line_style),
-            Span::styled(fx_cmd1, input_style(2)),
-            Span::styled(fx_val1, input_style(3)),
+            Span::styled(fx_cmd1, input_style(column)),
+            Span::styled(fx_val1, input_style(column + 1)),
             Span::styled(" ", line_style),
-            Span::styled(fx_cmd2, input_style(4)),
-            Span::styled(fx_val2, input_style(5)),
+            Span::styled(fx_cmd2, input_style(column + 2)),
+            Span::styled(fx_val2, input_style(column + 3)),
             Span::styled(" ", line_style),
         ]);
 
-        buf.set_line(area.left(), y, &spans, area.width);
+        buf.set_line(area.left(), y, &Line::from(spans), area.width);
         y += 1;
     }
 }
 
+/// Width of an instrument track including its borders
+fn track_width(note_columns: usize) -> u16 {
+    2 + NOTE_COLUMN_WIDTH * note_columns as u16 + EFFECTS_WIDTH
+}
+
+/// Black or white, whichever is easier to read on the background
+fn text_color(bg: Color) -> Color {
+    match bg {
+        Color::Rgb(r, g, b) if 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64 <= 128.0 => {
+            Color::White
+        }
+        _ => Color::Black,
+    }
+}
+
 fn is_current_line(app: &App, line: usize) -> bool {
     if app.state.selected_pattern != app.engine_state.current_pattern {
         false